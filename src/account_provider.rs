@@ -0,0 +1,542 @@
+#[cfg(test)]
+use std::collections::HashMap;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::IntegrityVerificationConfig, error::IntegrityVerificationError,
+    interfaces::AccountProvider,
+};
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcAccountInfoConfig};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::{
+    account::Account,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+};
+use tracing::{debug, warn};
+
+/// Commitment level accounts are fetched at
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcCommitment {
+    #[default]
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl From<RpcCommitment> for CommitmentLevel {
+    fn from(commitment: RpcCommitment) -> Self {
+        match commitment {
+            RpcCommitment::Processed => CommitmentLevel::Processed,
+            RpcCommitment::Confirmed => CommitmentLevel::Confirmed,
+            RpcCommitment::Finalized => CommitmentLevel::Finalized,
+        }
+    }
+}
+
+/// RPC node accounts are fetched from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcEndpointConfig {
+    pub url: String,
+    // Requests sent to the node at most, unlimited if not set
+    #[serde(default)]
+    pub max_requests_per_second: Option<f64>,
+}
+
+impl RpcEndpointConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.url.is_empty() {
+            return Err("url is empty".to_string());
+        }
+        if matches!(self.max_requests_per_second, Some(rate) if rate <= 0.0) {
+            return Err("max_requests_per_second must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Account fetches sent to an RPC node
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccountFetches {
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+impl AccountFetches {
+    pub fn merge(&mut self, other: &AccountFetches) {
+        self.succeeded += other.succeeded;
+        self.failed += other.failed;
+    }
+
+    /// Fetches made since `earlier` was taken
+    pub fn since(&self, earlier: &AccountFetches) -> Self {
+        Self {
+            succeeded: self.succeeded - earlier.succeeded,
+            failed: self.failed - earlier.failed,
+        }
+    }
+}
+
+/// Report of the account fetches of every RPC node, empty without nodes
+pub struct AccountFetchesReport<'a>(pub &'a BTreeMap<String, AccountFetches>);
+
+impl fmt::Display for AccountFetchesReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        write!(f, "\n---\nAccount fetches:\nendpoint - succeeded - failed")?;
+        for (endpoint, fetches) in self.0.iter() {
+            write!(
+                f,
+                "\n{} - {} - {}",
+                endpoint, fetches.succeeded, fetches.failed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// URL of an RPC node without its path and query, which often carry an API
+/// key, to be shown in reports
+pub fn endpoint_origin(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| "<invalid url>".to_string())
+}
+
+/// Returns the provider of the accounts proofs are validated against, the
+/// dumps directory if configured and the RPC endpoints otherwise
+pub fn account_provider_from_config(
+    config: &IntegrityVerificationConfig,
+) -> Box<dyn AccountProvider + Send + Sync> {
+    match &config.account_dumps_path {
+        Some(path) => Box::new(DumpDirAccountProvider::new(path)),
+        None => {
+            let endpoints = if config.rpc_endpoints.is_empty() {
+                vec![RpcEndpointConfig {
+                    url: config.rpc_endpoint.clone(),
+                    max_requests_per_second: None,
+                }]
+            } else {
+                config.rpc_endpoints.clone()
+            };
+            Box::new(RpcAccountProvider::new(&endpoints, config.rpc_commitment))
+        }
+    }
+}
+
+/// Spaces requests evenly so that no more than the given number are sent per
+/// second
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Takes the next request slot if it is already due
+    fn try_acquire(&self) -> bool {
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        if *next > now {
+            return false;
+        }
+        *next = now + self.interval;
+        true
+    }
+
+    /// Takes the next request slot, waiting until it is due
+    async fn acquire(&self) {
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        tokio::time::sleep_until(at.into()).await;
+    }
+}
+
+struct RpcEndpoint {
+    url: String,
+    rpc_client: RpcClient,
+    rate_limiter: Option<RateLimiter>,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl RpcEndpoint {
+    fn fetches(&self) -> AccountFetches {
+        AccountFetches {
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.rate_limiter
+            .as_ref()
+            .map_or(true, RateLimiter::try_acquire)
+    }
+
+    async fn acquire(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+    }
+}
+
+/// Fetches accounts from live nodes, tried in order: a node that fails, lags
+/// behind the requested slot or is at its rate limit is passed over for the
+/// next one
+pub struct RpcAccountProvider {
+    endpoints: Vec<RpcEndpoint>,
+    commitment: CommitmentLevel,
+}
+
+impl RpcAccountProvider {
+    pub fn new(endpoints: &[RpcEndpointConfig], commitment: RpcCommitment) -> Self {
+        Self {
+            endpoints: endpoints
+                .iter()
+                .map(|endpoint| RpcEndpoint {
+                    url: endpoint.url.clone(),
+                    rpc_client: RpcClient::new(endpoint.url.clone()),
+                    rate_limiter: endpoint.max_requests_per_second.map(RateLimiter::new),
+                    succeeded: AtomicU64::new(0),
+                    failed: AtomicU64::new(0),
+                })
+                .collect(),
+            commitment: commitment.into(),
+        }
+    }
+
+    async fn fetch(
+        &self,
+        endpoint: &RpcEndpoint,
+        pubkey: &Pubkey,
+        min_slot: Option<u64>,
+    ) -> Result<Option<(Vec<u8>, u64)>, IntegrityVerificationError> {
+        let fetched = self.fetch_from(endpoint, pubkey, min_slot).await;
+        match &fetched {
+            Ok(_) => endpoint.succeeded.fetch_add(1, Ordering::Relaxed),
+            Err(e) => {
+                warn!("Fetching account {} from {}: {}", pubkey, endpoint.url, e);
+                endpoint.failed.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+        fetched
+    }
+
+    async fn fetch_from(
+        &self,
+        endpoint: &RpcEndpoint,
+        pubkey: &Pubkey,
+        min_slot: Option<u64>,
+    ) -> Result<Option<(Vec<u8>, u64)>, IntegrityVerificationError> {
+        let response = endpoint
+            .rpc_client
+            .get_account_with_config(
+                pubkey,
+                RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64Zstd),
+                    commitment: Some(CommitmentConfig {
+                        commitment: self.commitment,
+                    }),
+                    min_context_slot: min_slot,
+                    data_slice: None,
+                },
+            )
+            .await?;
+        debug!(
+            "Account {} fetched from {} at slot {}",
+            pubkey, endpoint.url, response.context.slot
+        );
+
+        Ok(response
+            .value
+            .map(|account| (account.data, response.context.slot)))
+    }
+}
+
+#[async_trait]
+impl AccountProvider for RpcAccountProvider {
+    async fn get_account_data(
+        &self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, IntegrityVerificationError> {
+        Ok(self
+            .get_account_at_slot(pubkey, None)
+            .await?
+            .map(|(data, _)| data))
+    }
+
+    async fn get_account_at_slot(
+        &self,
+        pubkey: &Pubkey,
+        min_slot: Option<u64>,
+    ) -> Result<Option<(Vec<u8>, u64)>, IntegrityVerificationError> {
+        let mut last_error = None;
+        let mut rate_limited = Vec::new();
+        for endpoint in self.endpoints.iter() {
+            if !endpoint.try_acquire() {
+                rate_limited.push(endpoint);
+                continue;
+            }
+            match self.fetch(endpoint, pubkey, min_slot).await {
+                Ok(account) => return Ok(account),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        // Every available node failed, the rate limited ones are waited for
+        for endpoint in rate_limited {
+            endpoint.acquire().await;
+            match self.fetch(endpoint, pubkey, min_slot).await {
+                Ok(account) => return Ok(account),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            IntegrityVerificationError::ValidateConfig("rpc_endpoints".to_string())
+        }))
+    }
+
+    fn account_fetches(&self) -> BTreeMap<String, AccountFetches> {
+        let mut fetches = BTreeMap::new();
+        for endpoint in self.endpoints.iter() {
+            fetches
+                .entry(endpoint_origin(&endpoint.url))
+                .or_insert_with(AccountFetches::default)
+                .merge(&endpoint.fetches());
+        }
+        fetches
+    }
+}
+
+/// Account dump as written by `solana account <pubkey> --output json`
+#[derive(Deserialize)]
+struct AccountDump {
+    account: UiAccount,
+}
+
+/// Reads accounts from a directory of `<pubkey>.json` dumps, so proofs can
+/// be validated offline or against a historical snapshot
+pub struct DumpDirAccountProvider {
+    dir: PathBuf,
+}
+
+impl DumpDirAccountProvider {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+}
+
+#[async_trait]
+impl AccountProvider for DumpDirAccountProvider {
+    async fn get_account_data(
+        &self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, IntegrityVerificationError> {
+        let path = self.dir.join(format!("{}.json", pubkey));
+        let dump = match tokio::fs::read(&path).await {
+            Ok(dump) => dump,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let dump: AccountDump = serde_json::from_slice(&dump)?;
+        let account = dump.account.decode::<Account>().ok_or_else(|| {
+            IntegrityVerificationError::CannotGetResponseField(format!(
+                "data of {}",
+                path.display()
+            ))
+        })?;
+
+        Ok(Some(account.data))
+    }
+}
+
+/// Serves the accounts it was given, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct InMemoryAccountProvider {
+    accounts: HashMap<Pubkey, Vec<u8>>,
+}
+
+#[cfg(test)]
+impl InMemoryAccountProvider {
+    pub fn insert(&mut self, pubkey: Pubkey, data: Vec<u8>) {
+        self.accounts.insert(pubkey, data);
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl AccountProvider for InMemoryAccountProvider {
+    async fn get_account_data(
+        &self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, IntegrityVerificationError> {
+        Ok(self.accounts.get(pubkey).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        endpoint_origin, AccountFetches, DumpDirAccountProvider, RateLimiter, RpcAccountProvider,
+        RpcCommitment, RpcEndpointConfig,
+    };
+    use crate::{api::test_json_rpc_server, interfaces::AccountProvider};
+    use serde_json::json;
+    use solana_program::pubkey::Pubkey;
+    use std::{
+        sync::atomic::Ordering,
+        time::{Duration, Instant},
+    };
+
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        let rate_limiter = RateLimiter::new(20.0);
+        assert!(rate_limiter.try_acquire());
+        assert!(!rate_limiter.try_acquire());
+
+        let start = Instant::now();
+        rate_limiter.acquire().await;
+        rate_limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn test_rpc_failover() {
+        let (failing, failing_requests) = test_json_rpc_server(Duration::ZERO, |request| {
+            json!({"jsonrpc": "2.0", "id": request["id"], "error": {
+                "code": -32016,
+                "message": "Minimum context slot has not been reached",
+            }})
+        })
+        .await;
+        let (working, working_requests) = test_json_rpc_server(Duration::ZERO, |request| {
+            json!({"jsonrpc": "2.0", "id": request["id"], "result": {
+                "context": {"slot": 7},
+                "value": {
+                    "data": ["AQID", "base64"],
+                    "executable": false,
+                    "lamports": 1,
+                    "owner": Pubkey::default().to_string(),
+                    "rentEpoch": 0,
+                    "space": 3,
+                },
+            }})
+        })
+        .await;
+        let endpoint = |url: &str, max_requests_per_second| RpcEndpointConfig {
+            url: url.to_string(),
+            max_requests_per_second,
+        };
+        let requests = || {
+            (
+                failing_requests.load(Ordering::Relaxed),
+                working_requests.load(Ordering::Relaxed),
+            )
+        };
+        let pubkey = Pubkey::new_unique();
+
+        // The failing node comes first, the next one serves the account
+        let provider = RpcAccountProvider::new(
+            &[endpoint(&failing, None), endpoint(&working, None)],
+            RpcCommitment::default(),
+        );
+        assert_eq!(
+            Some((vec![1, 2, 3], 7)),
+            provider
+                .get_account_at_slot(&pubkey, Some(5))
+                .await
+                .unwrap()
+        );
+        assert_eq!((1, 1), requests());
+
+        // The rate limited node is passed over, then waited for once the
+        // other one failed
+        let provider = RpcAccountProvider::new(
+            &[endpoint(&working, Some(10.0)), endpoint(&failing, None)],
+            RpcCommitment::default(),
+        );
+        provider.get_account_at_slot(&pubkey, None).await.unwrap();
+        assert_eq!((1, 2), requests());
+        let start = Instant::now();
+        provider.get_account_at_slot(&pubkey, None).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!((2, 3), requests());
+
+        let fetches = provider.account_fetches();
+        assert_eq!(
+            AccountFetches {
+                succeeded: 2,
+                failed: 0
+            },
+            fetches[&endpoint_origin(&working)]
+        );
+        assert_eq!(
+            AccountFetches {
+                succeeded: 0,
+                failed: 1
+            },
+            fetches[&endpoint_origin(&failing)]
+        );
+        assert_eq!(
+            "https://rpc.example",
+            endpoint_origin("https://rpc.example/?api-key=secret")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dump_dir_account_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let pubkey = Pubkey::new_unique();
+        std::fs::write(
+            dir.path().join(format!("{}.json", pubkey)),
+            format!(
+                r#"{{"pubkey":"{}","account":{{"lamports":1,"data":["AQID","base64"],"owner":"{}","executable":false,"rentEpoch":0,"space":3}}}}"#,
+                pubkey,
+                Pubkey::default()
+            ),
+        )
+        .unwrap();
+
+        let provider = DumpDirAccountProvider::new(dir.path().to_str().unwrap());
+        assert_eq!(
+            Some(vec![1, 2, 3]),
+            provider.get_account_data(&pubkey).await.unwrap()
+        );
+        assert_eq!(
+            None,
+            provider
+                .get_account_data(&Pubkey::new_unique())
+                .await
+                .unwrap()
+        );
+    }
+}
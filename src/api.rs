@@ -1,15 +1,40 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use crate::error::IntegrityVerificationError;
+use hyper::client::connect::HttpInfo;
 use reqwest::Client;
 
+/// Phases of a single request. Time to first byte covers everything until the
+/// response headers arrive: connecting and the TLS handshake when
+/// `new_connection` is set, sending the request and the server time
+#[derive(Debug, Clone, Copy)]
+pub struct RequestTimings {
+    pub time_to_first_byte: Duration,
+    pub body_transfer: Duration,
+    pub parse: Duration,
+    pub response_bytes: u64,
+    // Set if the request opened a connection instead of reusing a pooled one
+    pub new_connection: bool,
+}
+
 #[derive(Debug)]
 pub struct IntegrityVerificationApi {
     client: Client,
+    // Local addresses of the connections the client opened so far, a response
+    // from any other address came over a new connection
+    connections: Mutex<HashSet<SocketAddr>>,
 }
 
 impl IntegrityVerificationApi {
     pub fn new() -> Self {
         Self {
             client: Client::new(),
+            connections: Mutex::new(HashSet::new()),
         }
     }
 
@@ -18,6 +43,19 @@ impl IntegrityVerificationApi {
         url: &str,
         body: &str,
     ) -> Result<serde_json::Value, IntegrityVerificationError> {
+        self.make_timed_request(url, body)
+            .await
+            .map(|(response, _)| response)
+    }
+
+    /// Same as `make_request`, but also returns how long every phase of the
+    /// request took
+    pub async fn make_timed_request(
+        &self,
+        url: &str,
+        body: &str,
+    ) -> Result<(serde_json::Value, RequestTimings), IntegrityVerificationError> {
+        let start = Instant::now();
         let resp = self
             .client
             .post(url)
@@ -25,6 +63,11 @@ impl IntegrityVerificationApi {
             .body(body.to_owned())
             .send()
             .await?;
+        let time_to_first_byte = start.elapsed();
+        let new_connection = resp
+            .extensions()
+            .get::<HttpInfo>()
+            .is_some_and(|info| self.connections.lock().unwrap().insert(info.local_addr()));
 
         let code = resp.status();
 
@@ -34,8 +77,109 @@ impl IntegrityVerificationApi {
             ));
         }
 
+        let body_start = Instant::now();
         let resp_body = resp.text().await?;
+        let body_transfer = body_start.elapsed();
+
+        let parse_start = Instant::now();
+        let response = serde_json::from_str(resp_body.as_str())?;
+        let parse = parse_start.elapsed();
+
+        Ok((
+            response,
+            RequestTimings {
+                time_to_first_byte,
+                body_transfer,
+                parse,
+                response_bytes: resp_body.len() as u64,
+                new_connection,
+            },
+        ))
+    }
+}
+
+/// Serves JSON-RPC over HTTP on a local port, answering every request with
+/// `handler` after `delay`, for tests. Returns the URL of the server and the
+/// number of requests it received
+#[cfg(test)]
+pub async fn test_json_rpc_server<F>(
+    delay: Duration,
+    handler: F,
+) -> (String, std::sync::Arc<std::sync::atomic::AtomicU64>)
+where
+    F: Fn(&serde_json::Value) -> serde_json::Value + Send + Sync + 'static,
+{
+    use std::sync::{atomic::AtomicU64, atomic::Ordering, Arc};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicU64::new(0));
+    let handler = Arc::new(handler);
+
+    let received = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (handler, received) = (handler.clone(), received.clone());
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                loop {
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let line = line.trim_end().to_ascii_lowercase();
+                        if line.is_empty() {
+                            break;
+                        }
+                        if let Some(length) = line.strip_prefix("content-length:") {
+                            content_length = length.trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    stream.read_exact(&mut body).await.unwrap();
+                    received.fetch_add(1, Ordering::Relaxed);
+
+                    tokio::time::sleep(delay).await;
+                    let response =
+                        handler(&serde_json::from_slice(&body).unwrap_or_default()).to_string();
+                    let reply = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                        response.len(),
+                        response
+                    );
+                    if stream.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    (url, requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_json_rpc_server, IntegrityVerificationApi};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_new_connections() {
+        let (url, _) = test_json_rpc_server(Duration::ZERO, |_| json!({"result": {}})).await;
+        let api = IntegrityVerificationApi::new();
 
-        Ok(serde_json::from_str(resp_body.as_str())?)
+        let (_, first) = api.make_timed_request(&url, "{}").await.unwrap();
+        assert!(first.new_connection);
+        let (_, second) = api.make_timed_request(&url, "{}").await.unwrap();
+        assert!(!second.new_connection);
+        let (_, other_client) = IntegrityVerificationApi::new()
+            .make_timed_request(&url, "{}")
+            .await
+            .unwrap();
+        assert!(other_client.new_connection);
     }
 }
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    account_provider::endpoint_origin,
+    config::IntegrityVerificationConfig,
+    error::IntegrityVerificationError,
+    file_keys_fetcher::KeySelection,
+    performance_comparison::delta_percent,
+    performance_measurement::{LoadProfile, Stats, Summary},
+};
+use serde_derive::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+const REDACTED: &str = "<redacted>";
+
+/// Config as saved with the results, without the RPC URLs and the paths and
+/// queries of the hosts, which often carry API keys, and the agent token
+fn redacted_config(
+    config: &IntegrityVerificationConfig,
+) -> Result<serde_json::Value, IntegrityVerificationError> {
+    let mut config = config.clone();
+    config.reference_host = endpoint_origin(&config.reference_host);
+    config.testing_host = endpoint_origin(&config.testing_host);
+    if !config.rpc_endpoint.is_empty() {
+        config.rpc_endpoint = REDACTED.to_string();
+    }
+    for endpoint in config.rpc_endpoints.iter_mut() {
+        endpoint.url = REDACTED.to_string();
+    }
+    if !config.agent_token.is_empty() {
+        config.agent_token = REDACTED.to_string();
+    }
+    Ok(serde_json::to_value(config)?)
+}
+
+/// Final summary of a performance run, stored to compare later runs against
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PerformanceResults {
+    pub seed: u64,
+    #[serde(default)]
+    pub key_selection: KeySelection,
+    // Set if the run was interrupted and covers less than the planned duration
+    #[serde(default)]
+    pub partial: bool,
+    pub config: serde_json::Value,
+    pub overall: Summary,
+    pub methods: BTreeMap<String, Summary>,
+    // Requests sent during the warm-up, not compared against the baseline
+    #[serde(default)]
+    pub warm_up: Option<Summary>,
+}
+
+impl PerformanceResults {
+    pub fn new(
+        config: &IntegrityVerificationConfig,
+        profile: &LoadProfile,
+        stats: &Stats,
+    ) -> Result<Self, IntegrityVerificationError> {
+        let duration = stats.duration(config.test_duration_time);
+        Ok(Self {
+            seed: profile.seed(),
+            key_selection: profile.key_selection(),
+            partial: stats.interrupted_after().is_some(),
+            config: redacted_config(config)?,
+            overall: Summary::new(stats.overall(), duration),
+            methods: stats
+                .methods()
+                .iter()
+                .map(|(method, method_stats)| {
+                    (method.clone(), Summary::new(method_stats, duration))
+                })
+                .collect(),
+            warm_up: stats
+                .warm_up()
+                .map(|warm_up| Summary::new(warm_up.overall(), config.warm_up_duration)),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), IntegrityVerificationError> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, IntegrityVerificationError> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(data.as_str())?)
+    }
+}
+
+/// Returns a description of every p95 or error rate regression of `current`
+/// beyond the tolerances
+pub fn find_regressions(
+    baseline: &PerformanceResults,
+    current: &PerformanceResults,
+    p95_tolerance_percent: f64,
+    error_rate_tolerance: f64,
+) -> Vec<String> {
+    let mut regressions = Vec::new();
+    let mut check = |scope: &str, baseline: &Summary, current: &Summary| {
+        let p95_delta = delta_percent(baseline.p95 as f64, current.p95 as f64);
+        info!(
+            "{}: p95 {} ms -> {} ms ({:+.2}%), error rate {:.4} -> {:.4}",
+            scope, baseline.p95, current.p95, p95_delta, baseline.error_rate, current.error_rate
+        );
+        if p95_delta > p95_tolerance_percent {
+            regressions.push(format!(
+                "{}: p95 regressed from {} ms to {} ms",
+                scope, baseline.p95, current.p95
+            ));
+        }
+        if current.error_rate - baseline.error_rate > error_rate_tolerance {
+            regressions.push(format!(
+                "{}: error rate regressed from {:.4} to {:.4}",
+                scope, baseline.error_rate, current.error_rate
+            ));
+        }
+    };
+
+    check("overall", &baseline.overall, &current.overall);
+    for (method, baseline_summary) in baseline.methods.iter() {
+        match current.methods.get(method) {
+            Some(current_summary) => check(method, baseline_summary, current_summary),
+            None => warn!("{} from the baseline was not requested in this run", method),
+        }
+    }
+
+    regressions
+}
+
+pub fn compare_with_baseline(
+    config: &IntegrityVerificationConfig,
+    current: &PerformanceResults,
+    baseline_path: &str,
+) -> Result<(), IntegrityVerificationError> {
+    let baseline = PerformanceResults::load(baseline_path)?;
+    if baseline.seed != current.seed {
+        warn!(
+            "Baseline was recorded with seed {}, current run used {}",
+            baseline.seed, current.seed
+        );
+    }
+    if baseline.key_selection != current.key_selection {
+        warn!(
+            "Baseline was recorded with {} key selection, current run used {}",
+            baseline.key_selection, current.key_selection
+        );
+    }
+
+    let regressions = find_regressions(
+        &baseline,
+        current,
+        config.baseline_p95_tolerance_percent,
+        config.baseline_error_rate_tolerance,
+    );
+    if regressions.is_empty() {
+        info!("No regressions against baseline {}", baseline_path);
+        return Ok(());
+    }
+    for regression in regressions.iter() {
+        error!("{}", regression);
+    }
+
+    Err(IntegrityVerificationError::BaselineRegression(
+        regressions.join("; "),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_regressions, redacted_config, PerformanceResults};
+    use crate::file_keys_fetcher::KeySelection;
+    use crate::performance_measurement::Summary;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn results(p95: u64, error_rate: f64) -> PerformanceResults {
+        let summary = Summary {
+            requests: 100,
+            rps: 10.0,
+            p50: p95 / 2,
+            p95,
+            p99: p95 * 2,
+            error_rate,
+            bytes_per_sec: 0.0,
+        };
+        PerformanceResults {
+            seed: 0,
+            key_selection: KeySelection::Uniform,
+            partial: false,
+            config: serde_json::Value::Null,
+            overall: summary,
+            methods: BTreeMap::from([("getAsset".to_string(), summary)]),
+            warm_up: None,
+        }
+    }
+
+    #[test]
+    fn test_find_regressions() {
+        assert!(find_regressions(&results(100, 0.0), &results(109, 0.005), 10.0, 0.01).is_empty());
+        assert_eq!(
+            vec![
+                "overall: p95 regressed from 100 ms to 120 ms",
+                "overall: error rate regressed from 0.0000 to 0.0500",
+                "getAsset: p95 regressed from 100 ms to 120 ms",
+                "getAsset: error rate regressed from 0.0000 to 0.0500",
+            ],
+            find_regressions(&results(100, 0.0), &results(120, 0.05), 10.0, 0.01)
+        );
+    }
+
+    #[test]
+    fn test_redacted_config() {
+        let config = serde_json::from_value(json!({
+            "reference_host": "https://reference/secret",
+            "testing_host": "https://testing:8899/?api-key=secret",
+            "testing_file_path": "keys.txt",
+            "num_of_virtual_users": 1,
+            "test_duration_time": 1,
+            "rpc_endpoint": "https://rpc/?api-key=secret",
+            "rpc_endpoints": [{"url": "https://backup/?api-key=secret"}],
+            "agent_token": "secret",
+        }))
+        .unwrap();
+        let saved = redacted_config(&config).unwrap();
+        assert!(!saved.to_string().contains("secret"));
+        assert_eq!("https://reference", saved["reference_host"]);
+        assert_eq!("https://testing:8899", saved["testing_host"]);
+    }
+}
@@ -0,0 +1,325 @@
+use std::{fmt, future::Future};
+
+use crate::{
+    config::IntegrityVerificationConfig,
+    error::IntegrityVerificationError,
+    performance_measurement::{run_load_stage, LoadProfile, Stats},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+const SLO_PERCENTILE: f64 = 99.0;
+
+/// Service level objective a load stage has to meet to be considered sustainable
+pub struct Slo {
+    pub max_p99_latency_ms: u64,
+    pub max_error_rate: f64,
+}
+
+impl Slo {
+    pub fn is_satisfied(&self, stats: &Stats) -> bool {
+        match stats.overall().percentile(SLO_PERCENTILE) {
+            Some(p99) => {
+                p99 <= self.max_p99_latency_ms
+                    && stats.overall().error_rate() <= self.max_error_rate
+            }
+            // Not a single successful response means the host is not keeping up
+            None => false,
+        }
+    }
+}
+
+pub struct StageResult {
+    pub virtual_users: usize,
+    pub stats: Stats,
+}
+
+pub struct CapacityReport {
+    pub step_duration: u64, // seconds
+    pub sustainable: Option<StageResult>,
+    pub first_violation: Option<usize>,
+    // Set if the search was stopped before it found the boundary
+    pub interrupted: bool,
+}
+
+impl fmt::Display for CapacityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.interrupted {
+            write!(
+                f,
+                "\nCapacity search was interrupted, results are partial\n"
+            )?;
+        }
+        let Some(sustainable) = &self.sustainable else {
+            return match self.first_violation {
+                Some(virtual_users) => write!(
+                    f,
+                    "\nSLO was violated already at {} virtual users",
+                    virtual_users
+                ),
+                None => write!(f, "\nNo load stage was completed"),
+            };
+        };
+
+        let duration = self.step_duration.max(1) as f64;
+        write!(
+            f,
+            "\nMaximum sustainable load: {} virtual users\nMaximum sustainable RPS: {:.2}\nP99 response time: {} ms\nError rate: {:.4}\n",
+            sustainable.virtual_users,
+            sustainable.stats.overall().successful_requests() as f64 / duration,
+            sustainable.stats.overall().percentile(SLO_PERCENTILE).unwrap_or_default(),
+            sustainable.stats.overall().error_rate(),
+        )?;
+        match self.first_violation {
+            Some(virtual_users) => writeln!(f, "SLO violated at: {} virtual users", virtual_users)?,
+            None => writeln!(f, "SLO was not violated up to the configured maximum")?,
+        }
+
+        write!(f, "---\nMethod mix:\nmethod - RPS")?;
+        let mut methods: Vec<_> = sustainable.stats.methods().iter().collect();
+        methods.sort_by_key(|(method, _)| *method);
+        for (method, method_stats) in methods {
+            write!(
+                f,
+                "\n{} - {:.2}",
+                method,
+                method_stats.successful_requests() as f64 / duration
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Doubles the number of virtual users starting from `start_virtual_users` until
+/// the SLO is violated or `max_virtual_users` is reached, then binary-searches
+/// the boundary between the last passing and the first failing stage. An
+/// interrupted stage is not judged and ends the search
+pub async fn search_capacity<F, Fut>(
+    start_virtual_users: usize,
+    max_virtual_users: usize,
+    slo: &Slo,
+    mut run_stage: F,
+) -> (Option<StageResult>, Option<usize>)
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Stats>,
+{
+    let mut sustainable: Option<StageResult> = None;
+    let mut first_violation = None;
+
+    let mut virtual_users = start_virtual_users;
+    loop {
+        let stats = run_stage(virtual_users).await;
+        log_stage(virtual_users, &stats);
+        if stats.interrupted_after().is_some() {
+            return (sustainable, first_violation);
+        }
+
+        if !slo.is_satisfied(&stats) {
+            first_violation = Some(virtual_users);
+            break;
+        }
+        sustainable = Some(StageResult {
+            virtual_users,
+            stats,
+        });
+        if virtual_users >= max_virtual_users {
+            break;
+        }
+        virtual_users = (virtual_users * 2).min(max_virtual_users);
+    }
+
+    if let (Some(mut high), Some(passed)) = (first_violation, sustainable.as_ref()) {
+        let mut low = passed.virtual_users;
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+            let stats = run_stage(middle).await;
+            log_stage(middle, &stats);
+            if stats.interrupted_after().is_some() {
+                return (sustainable, Some(high));
+            }
+
+            if slo.is_satisfied(&stats) {
+                low = middle;
+                sustainable = Some(StageResult {
+                    virtual_users: middle,
+                    stats,
+                });
+            } else {
+                high = middle;
+            }
+        }
+        first_violation = Some(high);
+    }
+
+    (sustainable, first_violation)
+}
+
+fn log_stage(virtual_users: usize, stats: &Stats) {
+    info!(
+        "Capacity stage: {} virtual users, {} requests, p99 {} ms, error rate {:.4}",
+        virtual_users,
+        stats.overall().total_requests(),
+        stats
+            .overall()
+            .percentile(SLO_PERCENTILE)
+            .unwrap_or_default(),
+        stats.overall().error_rate()
+    );
+}
+
+pub async fn run_capacity_search(
+    config: &IntegrityVerificationConfig,
+    cancel_token: CancellationToken,
+) -> Result<(), IntegrityVerificationError> {
+    let profile = LoadProfile::from_config(config).await?;
+    info!("Key selection: {}", profile.key_selection());
+    let slo = Slo {
+        max_p99_latency_ms: config.slo_p99_latency_ms,
+        max_error_rate: config.slo_max_error_rate,
+    };
+
+    let (sustainable, first_violation) = search_capacity(
+        config.capacity_start_virtual_users,
+        config.capacity_max_virtual_users,
+        &slo,
+        |virtual_users| {
+            run_load_stage(
+                virtual_users,
+                config.capacity_step_duration,
+                &config.testing_host,
+                &profile,
+                &cancel_token,
+            )
+        },
+    )
+    .await;
+
+    println!(
+        "{}",
+        CapacityReport {
+            step_duration: config.capacity_step_duration,
+            sustainable,
+            first_violation,
+            interrupted: cancel_token.is_cancelled(),
+        }
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{search_capacity, CapacityReport, Slo, StageResult};
+    use crate::performance_measurement::Stats;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn stage_stats(virtual_users: usize) -> Stats {
+        let mut stats = Stats::starting_at(Instant::now());
+        for _ in 0..100 {
+            // Latency grows linearly with the load
+            stats.record_success("getAsset", virtual_users as u64 * 10);
+        }
+        stats
+    }
+
+    #[tokio::test]
+    async fn test_search_capacity_finds_boundary() {
+        let slo = Slo {
+            max_p99_latency_ms: 370,
+            max_error_rate: 0.0,
+        };
+        let mut visited = Vec::new();
+
+        let (sustainable, first_violation) = search_capacity(1, 256, &slo, |virtual_users| {
+            visited.push(virtual_users);
+            async move { stage_stats(virtual_users) }
+        })
+        .await;
+
+        assert_eq!(37, sustainable.unwrap().virtual_users);
+        assert_eq!(Some(38), first_violation);
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 64, 48, 40, 36, 38, 37], visited);
+    }
+
+    #[tokio::test]
+    async fn test_search_capacity_stops_at_max() {
+        let slo = Slo {
+            max_p99_latency_ms: u64::MAX,
+            max_error_rate: 0.0,
+        };
+
+        let (sustainable, first_violation) =
+            search_capacity(3, 10, &slo, |virtual_users| async move {
+                stage_stats(virtual_users)
+            })
+            .await;
+
+        assert_eq!(10, sustainable.unwrap().virtual_users);
+        assert_eq!(None, first_violation);
+    }
+
+    #[tokio::test]
+    async fn test_search_capacity_stops_when_interrupted() {
+        let slo = Slo {
+            max_p99_latency_ms: 370,
+            max_error_rate: 0.0,
+        };
+        let mut visited = Vec::new();
+
+        let (sustainable, first_violation) = search_capacity(1, 256, &slo, |virtual_users| {
+            visited.push(virtual_users);
+            let mut stats = stage_stats(virtual_users);
+            if virtual_users == 40 {
+                stats.mark_interrupted(Duration::from_secs(1));
+            }
+            async move { stats }
+        })
+        .await;
+
+        // The interrupted stage passes the SLO but is not taken as sustainable
+        assert_eq!(32, sustainable.unwrap().virtual_users);
+        assert_eq!(Some(48), first_violation);
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 64, 48, 40], visited);
+    }
+
+    #[test]
+    fn test_capacity_report() {
+        let interrupted = CapacityReport {
+            step_duration: 10,
+            sustainable: None,
+            first_violation: None,
+            interrupted: true,
+        };
+        assert!(interrupted
+            .to_string()
+            .contains("No load stage was completed"));
+
+        let mut stats = Stats::starting_at(Instant::now());
+        for _ in 0..30 {
+            stats.record_success("getAsset", 10);
+            stats.record_success("getAssetProof", 10);
+        }
+        stats.record_failure("getAsset", Some(500));
+        let report = CapacityReport {
+            step_duration: 10,
+            sustainable: Some(StageResult {
+                virtual_users: 4,
+                stats,
+            }),
+            first_violation: Some(5),
+            interrupted: false,
+        }
+        .to_string();
+        // Failed requests count in neither the headline nor the method mix
+        assert!(
+            report.contains("Maximum sustainable RPS: 6.00"),
+            "{}",
+            report
+        );
+        assert!(report.contains("getAsset - 3.00"), "{}", report);
+        assert!(report.contains("getAssetProof - 3.00"), "{}", report);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::IntegrityVerificationError;
+use serde_json::Value;
+use solana_program::{keccak, pubkey::Pubkey};
+
+// Version of the Bubblegum leaf schema, hashed into every leaf
+const LEAF_SCHEMA_V1: u8 = 1;
+
+/// Hash of the compressed asset data that does not match the data it is
+/// computed from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LeafMismatch {
+    DataHash,
+    CreatorHash,
+    Leaf,
+}
+
+impl fmt::Display for LeafMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mismatch = match self {
+            LeafMismatch::DataHash => "data hash",
+            LeafMismatch::CreatorHash => "creator hash",
+            LeafMismatch::Leaf => "leaf",
+        };
+        write!(f, "{}", mismatch)
+    }
+}
+
+fn field<'a>(asset: &'a Value, path: &str) -> Result<&'a Value, IntegrityVerificationError> {
+    let value = path
+        .split('.')
+        .fold(asset, |value, segment| &value[segment]);
+    if value.is_null() {
+        return Err(IntegrityVerificationError::CannotGetResponseField(
+            path.to_string(),
+        ));
+    }
+    Ok(value)
+}
+
+fn str_field<'a>(asset: &'a Value, path: &str) -> Result<&'a str, IntegrityVerificationError> {
+    field(asset, path)?
+        .as_str()
+        .ok_or(IntegrityVerificationError::CannotGetResponseField(
+            path.to_string(),
+        ))
+}
+
+fn u64_field(asset: &Value, path: &str) -> Result<u64, IntegrityVerificationError> {
+    field(asset, path)?
+        .as_u64()
+        .ok_or(IntegrityVerificationError::CannotGetResponseField(
+            path.to_string(),
+        ))
+}
+
+fn bool_field(asset: &Value, path: &str) -> Result<bool, IntegrityVerificationError> {
+    field(asset, path)?
+        .as_bool()
+        .ok_or(IntegrityVerificationError::CannotGetResponseField(
+            path.to_string(),
+        ))
+}
+
+fn pubkey_field(asset: &Value, path: &str) -> Result<[u8; 32], IntegrityVerificationError> {
+    Ok(Pubkey::from_str(str_field(asset, path)?)?.to_bytes())
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+/// Index of the Bubblegum `TokenStandard` variant
+fn token_standard_index(token_standard: &str) -> Option<u8> {
+    match token_standard {
+        "NonFungible" => Some(0),
+        "FungibleAsset" => Some(1),
+        "Fungible" => Some(2),
+        "NonFungibleEdition" => Some(3),
+        _ => None,
+    }
+}
+
+/// Index of the Bubblegum `UseMethod` variant
+fn use_method_index(use_method: &str) -> Option<u8> {
+    match use_method {
+        "Burn" => Some(0),
+        "Multiple" => Some(1),
+        "Single" => Some(2),
+        _ => None,
+    }
+}
+
+/// Returns the number of creators of `asset` and their address, verified
+/// flag and share one after another
+fn creator_bytes(asset: &Value) -> Result<(u32, Vec<u8>), IntegrityVerificationError> {
+    let creators = field(asset, "creators")?.as_array().ok_or(
+        IntegrityVerificationError::CannotGetResponseField("creators".to_string()),
+    )?;
+
+    let mut bytes = Vec::new();
+    for creator in creators {
+        bytes.extend_from_slice(&pubkey_field(creator, "address")?);
+        bytes.push(bool_field(creator, "verified")? as u8);
+        bytes.push(u64_field(creator, "share")? as u8);
+    }
+    Ok((creators.len() as u32, bytes))
+}
+
+/// Hash of the creators of `asset` as Bubblegum computes it
+pub fn creator_hash(asset: &Value) -> Result<[u8; 32], IntegrityVerificationError> {
+    let (_, bytes) = creator_bytes(asset)?;
+    Ok(keccak::hash(&bytes).to_bytes())
+}
+
+/// Borsh serialization of the Bubblegum `MetadataArgs` of `asset`, with the
+/// collection verified as given since older DAS versions do not return it
+fn metadata_args(
+    asset: &Value,
+    collection_verified: bool,
+) -> Result<Vec<u8>, IntegrityVerificationError> {
+    let mut bytes = Vec::new();
+    write_string(&mut bytes, str_field(asset, "content.metadata.name")?);
+    write_string(&mut bytes, str_field(asset, "content.metadata.symbol")?);
+    write_string(&mut bytes, str_field(asset, "content.json_uri")?);
+    bytes.extend_from_slice(&(u64_field(asset, "royalty.basis_points")? as u16).to_le_bytes());
+    bytes.push(bool_field(asset, "royalty.primary_sale_happened")? as u8);
+    bytes.push(bool_field(asset, "mutable")? as u8);
+
+    match asset["supply"]["edition_nonce"].as_u64() {
+        Some(nonce) => bytes.extend_from_slice(&[1, nonce as u8]),
+        None => bytes.push(0),
+    }
+    match asset["content"]["metadata"]["token_standard"]
+        .as_str()
+        .and_then(token_standard_index)
+    {
+        Some(index) => bytes.extend_from_slice(&[1, index]),
+        None => bytes.push(0),
+    }
+
+    let collection = asset["grouping"]
+        .as_array()
+        .and_then(|groups| groups.iter().find(|g| g["group_key"] == "collection"));
+    match collection {
+        Some(collection) => {
+            bytes.push(1);
+            bytes.push(collection_verified as u8);
+            bytes.extend_from_slice(&pubkey_field(collection, "group_value")?);
+        }
+        None => bytes.push(0),
+    }
+
+    let uses = &asset["uses"];
+    match uses["use_method"].as_str().and_then(use_method_index) {
+        Some(index) => {
+            bytes.push(1);
+            bytes.push(index);
+            bytes.extend_from_slice(&u64_field(uses, "remaining")?.to_le_bytes());
+            bytes.extend_from_slice(&u64_field(uses, "total")?.to_le_bytes());
+        }
+        None => bytes.push(0),
+    }
+
+    // Token program version, compressed assets are minted as `Original`
+    bytes.push(0);
+
+    let (creators, creator_bytes) = creator_bytes(asset)?;
+    bytes.extend_from_slice(&creators.to_le_bytes());
+    bytes.extend_from_slice(&creator_bytes);
+
+    Ok(bytes)
+}
+
+/// Hashes of the metadata of `asset` as Bubblegum computes them, one for each
+/// possible collection verification if the response does not tell it
+pub fn data_hashes(asset: &Value) -> Result<Vec<[u8; 32]>, IntegrityVerificationError> {
+    let collection_verified = asset["grouping"].as_array().and_then(|groups| {
+        groups
+            .iter()
+            .find(|g| g["group_key"] == "collection")
+            .and_then(|collection| collection["verified"].as_bool())
+    });
+    let candidates = match collection_verified {
+        Some(verified) => vec![verified],
+        None => vec![true, false],
+    };
+
+    let seller_fee_basis_points = u64_field(asset, "royalty.basis_points")? as u16;
+    candidates
+        .into_iter()
+        .map(|verified| {
+            let metadata_hash = keccak::hash(&metadata_args(asset, verified)?);
+            Ok(keccak::hashv(&[
+                metadata_hash.as_ref(),
+                &seller_fee_basis_points.to_le_bytes(),
+            ])
+            .to_bytes())
+        })
+        .collect()
+}
+
+/// Hash of the Bubblegum leaf of `asset` built from the hashes it returns
+pub fn leaf_hash(asset: &Value) -> Result<[u8; 32], IntegrityVerificationError> {
+    let id = pubkey_field(asset, "id")?;
+    let owner = pubkey_field(asset, "ownership.owner")?;
+    let delegate = match asset["ownership"]["delegate"].as_str() {
+        Some(delegate) => Pubkey::from_str(delegate)?.to_bytes(),
+        None => owner,
+    };
+    let nonce = u64_field(asset, "compression.leaf_id")?;
+
+    Ok(keccak::hashv(&[
+        &[LEAF_SCHEMA_V1],
+        &id,
+        &owner,
+        &delegate,
+        &nonce.to_le_bytes(),
+        &pubkey_field(asset, "compression.data_hash")?,
+        &pubkey_field(asset, "compression.creator_hash")?,
+    ])
+    .to_bytes())
+}
+
+/// Checks that the hashes `getAsset` returns for a compressed asset are the
+/// ones of the data it serves, and that they hash to `leaf`
+pub fn check_asset_leaf(
+    asset: &Value,
+    leaf: &[u8; 32],
+) -> Result<Option<LeafMismatch>, IntegrityVerificationError> {
+    let asset = &asset["result"];
+    // Creators are part of the metadata as well, their hash tells more
+    if creator_hash(asset)? != pubkey_field(asset, "compression.creator_hash")? {
+        return Ok(Some(LeafMismatch::CreatorHash));
+    }
+    if !data_hashes(asset)?.contains(&pubkey_field(asset, "compression.data_hash")?) {
+        return Ok(Some(LeafMismatch::DataHash));
+    }
+    if leaf_hash(asset)? != *leaf {
+        return Ok(Some(LeafMismatch::Leaf));
+    }
+    Ok(None)
+}
+
+/// Returns a `getAsset` response of a compressed asset whose hashes match its
+/// data, for tests
+#[cfg(test)]
+pub fn test_compressed_asset(leaf_id: u64) -> Value {
+    let mut asset = serde_json::json!({
+        "id": Pubkey::new_unique().to_string(),
+        "content": {
+            "json_uri": "https://example.com/asset.json",
+            "metadata": {"name": "Asset", "symbol": "AST", "token_standard": "NonFungible"}
+        },
+        "grouping": [{"group_key": "collection", "group_value": Pubkey::new_unique().to_string()}],
+        "royalty": {"basis_points": 500, "primary_sale_happened": false},
+        "creators": [{"address": Pubkey::new_unique().to_string(), "share": 100, "verified": true}],
+        "ownership": {"owner": Pubkey::new_unique().to_string(), "delegate": null},
+        "supply": {"edition_nonce": null},
+        "mutable": true,
+        "compression": {"leaf_id": leaf_id},
+    });
+    let data_hash = data_hashes(&asset).unwrap()[0];
+    let creator_hash = creator_hash(&asset).unwrap();
+    asset["compression"]["data_hash"] = Pubkey::new_from_array(data_hash).to_string().into();
+    asset["compression"]["creator_hash"] = Pubkey::new_from_array(creator_hash).to_string().into();
+    serde_json::json!({ "result": asset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_asset_leaf, leaf_hash, test_compressed_asset, LeafMismatch};
+    use solana_program::pubkey::Pubkey;
+
+    #[test]
+    fn test_check_asset_leaf() {
+        let asset = test_compressed_asset(3);
+        let leaf = leaf_hash(&asset["result"]).unwrap();
+        assert_eq!(None, check_asset_leaf(&asset, &leaf).unwrap());
+
+        let mut renamed = asset.clone();
+        renamed["result"]["content"]["metadata"]["name"] = "Renamed".into();
+        assert_eq!(
+            Some(LeafMismatch::DataHash),
+            check_asset_leaf(&renamed, &leaf).unwrap()
+        );
+
+        let mut unverified = asset.clone();
+        unverified["result"]["creators"][0]["verified"] = false.into();
+        assert_eq!(
+            Some(LeafMismatch::CreatorHash),
+            check_asset_leaf(&unverified, &leaf).unwrap()
+        );
+
+        let mut transferred = asset.clone();
+        transferred["result"]["ownership"]["owner"] = Pubkey::new_unique().to_string().into();
+        assert_eq!(
+            Some(LeafMismatch::Leaf),
+            check_asset_leaf(&transferred, &leaf).unwrap()
+        );
+    }
+}
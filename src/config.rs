@@ -1,15 +1,93 @@
+use crate::account_provider::{RpcCommitment, RpcEndpointConfig};
+use crate::diff_checker::LOAD_METHODS;
 use crate::error::IntegrityVerificationError;
-use serde_derive::Deserialize;
+use crate::file_keys_fetcher::KeySelection;
+use crate::params_generation::ParamsDistribution;
+use crate::scenarios::Scenario;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 const fn default_test_retries() -> u64 {
     20
 }
 
-#[derive(Deserialize, Debug)]
+const fn default_capacity_start_virtual_users() -> usize {
+    1
+}
+
+const fn default_capacity_max_virtual_users() -> usize {
+    256
+}
+
+const fn default_capacity_step_duration() -> u64 {
+    30
+}
+
+const fn default_slo_p99_latency_ms() -> u64 {
+    1000
+}
+
+const fn default_slo_max_error_rate() -> f64 {
+    0.01
+}
+
+const fn default_comparison_sequence_length() -> usize {
+    10000
+}
+
+const fn default_comparison_tolerance_percent() -> f64 {
+    10.0
+}
+
+const fn default_baseline_p95_tolerance_percent() -> f64 {
+    10.0
+}
+
+const fn default_zipf_exponent() -> f64 {
+    1.0
+}
+
+const fn default_slowest_keys_report_size() -> usize {
+    10
+}
+
+const fn default_replay_speed() -> f64 {
+    1.0
+}
+
+const fn default_replay_max_in_flight() -> usize {
+    1024
+}
+
+const fn default_sweep_concurrency() -> usize {
+    16
+}
+
+const fn default_stop_timeout() -> u64 {
+    10
+}
+
+const fn default_tree_cache_max_age_millis() -> u64 {
+    2000
+}
+
+const fn default_baseline_error_rate_tolerance() -> f64 {
+    0.01
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IntegrityVerificationConfig {
     pub reference_host: String,
     pub testing_host: String,
+    // Not needed if `rpc_endpoints` is set
+    #[serde(default)]
     pub rpc_endpoint: String,
+    // Nodes accounts are fetched from instead of `rpc_endpoint`, in order of
+    // preference
+    #[serde(default)]
+    pub rpc_endpoints: Vec<RpcEndpointConfig>,
+    #[serde(default)]
+    pub rpc_commitment: RpcCommitment,
     pub testing_file_path: String,
     #[serde(default = "default_test_retries")]
     pub test_retries: u64,
@@ -19,6 +97,86 @@ pub struct IntegrityVerificationConfig {
     pub difference_filter_regexes: Vec<String>,
     pub num_of_virtual_users: usize,
     pub test_duration_time: u64, // seconds
+    #[serde(default = "default_capacity_start_virtual_users")]
+    pub capacity_start_virtual_users: usize,
+    #[serde(default = "default_capacity_max_virtual_users")]
+    pub capacity_max_virtual_users: usize,
+    #[serde(default = "default_capacity_step_duration")]
+    pub capacity_step_duration: u64, // seconds
+    #[serde(default = "default_slo_p99_latency_ms")]
+    pub slo_p99_latency_ms: u64,
+    #[serde(default = "default_slo_max_error_rate")]
+    pub slo_max_error_rate: f64,
+    // Relative weights of the keys file methods in performance tests,
+    // methods are picked uniformly if empty
+    #[serde(default)]
+    pub method_weights: HashMap<String, u32>,
+    #[serde(default)]
+    pub method_params: HashMap<String, ParamsDistribution>,
+    #[serde(default = "default_comparison_sequence_length")]
+    pub comparison_sequence_length: usize,
+    #[serde(default)]
+    pub comparison_concurrent: bool,
+    #[serde(default = "default_comparison_tolerance_percent")]
+    pub comparison_tolerance_percent: f64,
+    #[serde(default)]
+    pub time_series_csv_path: Option<String>,
+    #[serde(default)]
+    pub time_series_json_path: Option<String>,
+    // Random if not set, the one used is saved with the results
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default = "default_baseline_p95_tolerance_percent")]
+    pub baseline_p95_tolerance_percent: f64,
+    #[serde(default = "default_baseline_error_rate_tolerance")]
+    pub baseline_error_rate_tolerance: f64,
+    // Share of responses validated during load tests, 0 disables validation
+    #[serde(default)]
+    pub validation_sample_rate: f64,
+    #[serde(default)]
+    pub validate_proofs: bool,
+    // Directory of `solana account --output json` dumps named `<pubkey>.json`,
+    // proofs are validated against them instead of `rpc_endpoint` if set
+    #[serde(default)]
+    pub account_dumps_path: Option<String>,
+    // Milliseconds a tree account read is used for proof validations before
+    // it is read again, so a lagging host cannot match a long gone root
+    #[serde(default = "default_tree_cache_max_age_millis")]
+    pub tree_cache_max_age_millis: u64,
+    #[serde(default)]
+    pub key_selection: KeySelection,
+    #[serde(default = "default_zipf_exponent")]
+    pub zipf_exponent: f64,
+    // Seconds of load sent before the measured test duration and reported apart
+    #[serde(default)]
+    pub warm_up_duration: u64,
+    // User journeys replacing independent random requests when not empty
+    #[serde(default)]
+    pub scenarios: Vec<Scenario>,
+    // JSONL file of captured requests for the replay test
+    #[serde(default)]
+    pub replay_corpus_path: Option<String>,
+    #[serde(default = "default_replay_speed")]
+    pub replay_speed: f64,
+    #[serde(default = "default_replay_max_in_flight")]
+    pub replay_max_in_flight: usize,
+    // Tree whose leaves the tree sweep checks
+    #[serde(default)]
+    pub sweep_tree: Option<String>,
+    #[serde(default = "default_sweep_concurrency")]
+    pub sweep_concurrency: usize,
+    // Addresses of the agents generating the load of a controller run
+    #[serde(default)]
+    pub agents: Vec<String>,
+    // Shared secret the agents are started with
+    #[serde(default)]
+    pub agent_token: String,
+    // Number of keys in the slowest keys report, 0 disables the report
+    #[serde(default = "default_slowest_keys_report_size")]
+    pub slowest_keys_report_size: usize,
+    // Seconds workers get to finish in-flight requests once stopped
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout: u64,
 }
 
 pub fn setup_config(path: &str) -> Result<IntegrityVerificationConfig, IntegrityVerificationError> {
@@ -35,5 +193,138 @@ fn validate_config(config: &IntegrityVerificationConfig) -> Result<(), Integrity
             "test_retries".to_string(),
         ));
     }
+    if config.capacity_start_virtual_users < 1
+        || config.capacity_max_virtual_users < config.capacity_start_virtual_users
+    {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "capacity_start_virtual_users".to_string(),
+        ));
+    }
+    if !(0.0..=1.0).contains(&config.slo_max_error_rate) {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "slo_max_error_rate".to_string(),
+        ));
+    }
+    if !(0.0..=1.0).contains(&config.validation_sample_rate) {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "validation_sample_rate".to_string(),
+        ));
+    }
+    if config.zipf_exponent <= 0.0 {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "zipf_exponent".to_string(),
+        ));
+    }
+    if config.replay_speed <= 0.0 {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "replay_speed".to_string(),
+        ));
+    }
+    if config.replay_max_in_flight < 1 {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "replay_max_in_flight".to_string(),
+        ));
+    }
+    if config.sweep_concurrency < 1 {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "sweep_concurrency".to_string(),
+        ));
+    }
+    if config.rpc_endpoint.is_empty() && config.rpc_endpoints.is_empty() {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "rpc_endpoint".to_string(),
+        ));
+    }
+    for endpoint in config.rpc_endpoints.iter() {
+        endpoint.validate().map_err(|e| {
+            IntegrityVerificationError::ValidateConfig(format!(
+                "rpc_endpoints.{}: {}",
+                endpoint.url, e
+            ))
+        })?;
+    }
+    if !config.agents.is_empty() && config.agent_token.is_empty() {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "agent_token".to_string(),
+        ));
+    }
+    if config.comparison_sequence_length < 1 {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "comparison_sequence_length".to_string(),
+        ));
+    }
+    // A misspelled method would silently get no weight or no narrowed params
+    let configured_methods = config
+        .method_weights
+        .keys()
+        .map(|method| ("method_weights", method))
+        .chain(
+            config
+                .method_params
+                .keys()
+                .map(|method| ("method_params", method)),
+        );
+    for (field, method) in configured_methods {
+        if !LOAD_METHODS.contains(&method.as_str()) {
+            return Err(IntegrityVerificationError::ValidateConfig(format!(
+                "{}.{}: unknown method",
+                field, method
+            )));
+        }
+    }
+    for (method, distribution) in config.method_params.iter() {
+        distribution.validate().map_err(|e| {
+            IntegrityVerificationError::ValidateConfig(format!("method_params.{}: {}", method, e))
+        })?;
+    }
+    for scenario in config.scenarios.iter() {
+        scenario.validate().map_err(|e| {
+            IntegrityVerificationError::ValidateConfig(format!(
+                "scenarios.{}: {}",
+                scenario.name, e
+            ))
+        })?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_config, IntegrityVerificationConfig};
+    use serde_json::json;
+
+    fn config(
+        method_weights: serde_json::Value,
+        method_params: serde_json::Value,
+    ) -> IntegrityVerificationConfig {
+        serde_json::from_value(json!({
+            "reference_host": "http://reference",
+            "testing_host": "http://testing",
+            "testing_file_path": "keys.txt",
+            "num_of_virtual_users": 1,
+            "test_duration_time": 1,
+            "rpc_endpoint": "http://rpc",
+            "method_weights": method_weights,
+            "method_params": method_params,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unknown_methods_rejected() {
+        assert!(validate_config(&config(
+            json!({"getAsset": 1, "getAssetsByOwner": 2}),
+            json!({"getAssetsByOwner": {"max_limit": 10}})
+        ))
+        .is_ok());
+
+        let err = validate_config(&config(json!({"getAssets": 1}), json!({})))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("method_weights.getAssets"), "{}", err);
+        let err = validate_config(&config(json!({}), json!({"getAssetByOwner": {}})))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("method_params.getAssetByOwner"), "{}", err);
+    }
+}
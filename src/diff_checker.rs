@@ -3,34 +3,27 @@ use crate::config::IntegrityVerificationConfig;
 use crate::error::IntegrityVerificationError;
 use crate::interfaces::IntegrityVerificationKeysFetcher;
 use crate::params_generation::{
-    generate_get_asset_params, generate_get_asset_proof_params,
+    generate_get_asset_params, generate_get_asset_proof_params, generate_get_asset_proofs_params,
     generate_get_assets_by_authority_params, generate_get_assets_by_creator_params,
     generate_get_assets_by_group_params, generate_get_assets_by_owner_params,
-    generate_get_signatures_for_asset, generate_get_token_accounts,
+    generate_get_signatures_for_asset, generate_get_token_accounts, ParamsDistribution,
 };
+use crate::proof_validator::{ProofStatus, ProofValidator};
 use crate::requests::Body;
-use crate::{_check_proof, check_proof};
-use anchor_lang::AnchorDeserialize;
 use assert_json_diff::{assert_json_matches_no_panic, CompareMode, Config};
 use regex::Regex;
 use serde_json::{json, Value};
-use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_program::pubkey::Pubkey;
-use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
-use spl_account_compression::canopy::fill_in_proof_from_canopy;
-use spl_account_compression::state::{
-    merkle_tree_get_size, ConcurrentMerkleTreeHeader, CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1,
-};
-use spl_account_compression::zero_copy::ZeroCopy;
-use std::collections::HashMap;
-use std::str::FromStr;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::time::Duration;
 use tokio::sync::Mutex;
-use tracing::error;
 use tracing::log::info;
+use tracing::{error, warn};
 
 pub const GET_ASSET_METHOD: &str = "getAsset";
 pub const GET_ASSET_PROOF_METHOD: &str = "getAssetProof";
+pub const GET_ASSET_PROOFS_METHOD: &str = "getAssetProofs";
+pub const GET_ASSET_PROOF_BATCH_METHOD: &str = "getAssetProofBatch";
 pub const GET_ASSET_BY_OWNER_METHOD: &str = "getAssetsByOwner";
 pub const GET_ASSET_BY_AUTHORITY_METHOD: &str = "getAssetsByAuthority";
 pub const GET_ASSET_BY_GROUP_METHOD: &str = "getAssetsByGroup";
@@ -41,12 +34,76 @@ pub const GET_TOKEN_ACCOUNTS_BY_MINT: &str = "getTokenAccountsByMint";
 pub const GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT: &str = "getTokenAccountsByOwnerAndMint";
 pub const GET_SIGNATURES_FOR_ASSET: &str = "getSignaturesForAsset";
 
+/// Keys file methods the load tests can generate requests for
+pub const LOAD_METHODS: [&str; 12] = [
+    GET_ASSET_METHOD,
+    GET_ASSET_PROOF_METHOD,
+    GET_ASSET_PROOFS_METHOD,
+    GET_ASSET_PROOF_BATCH_METHOD,
+    GET_ASSET_BY_OWNER_METHOD,
+    GET_ASSET_BY_AUTHORITY_METHOD,
+    GET_ASSET_BY_GROUP_METHOD,
+    GET_ASSET_BY_CREATOR_METHOD,
+    GET_TOKEN_ACCOUNTS_BY_OWNER,
+    GET_TOKEN_ACCOUNTS_BY_MINT,
+    GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT,
+    GET_SIGNATURES_FOR_ASSET,
+];
+
 const REQUESTS_INTERVAL_MILLIS: u64 = 1500;
 
+/// Outcome of validating the proofs both hosts return for the same asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ProofComparison {
+    BothValidSame,
+    BothValidDifferent,
+    TestingInvalid,
+    ReferenceInvalid,
+    BothInvalid,
+}
+
+impl ProofComparison {
+    fn new(reference: ProofStatus, testing: ProofStatus, responses_differ: bool) -> Self {
+        match (reference.is_valid(), testing.is_valid()) {
+            (true, true) if responses_differ => ProofComparison::BothValidDifferent,
+            (true, true) => ProofComparison::BothValidSame,
+            (true, false) => ProofComparison::TestingInvalid,
+            (false, true) => ProofComparison::ReferenceInvalid,
+            (false, false) => ProofComparison::BothInvalid,
+        }
+    }
+}
+
+impl fmt::Display for ProofComparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let comparison = match self {
+            ProofComparison::BothValidSame => "both valid and same",
+            ProofComparison::BothValidDifferent => "both valid but different",
+            ProofComparison::TestingInvalid => "testing invalid",
+            ProofComparison::ReferenceInvalid => "reference invalid",
+            ProofComparison::BothInvalid => "both invalid",
+        };
+        write!(f, "{}", comparison)
+    }
+}
+
 #[derive(Default)]
 struct TestingResult {
     total_tests: u64,
     failed_tests: u64,
+    reference_proofs: BTreeMap<ProofStatus, u64>,
+    testing_proofs: BTreeMap<ProofStatus, u64>,
+    proof_comparisons: BTreeMap<ProofComparison, u64>,
+    // Tests passed on a valid testing proof although the responses differ
+    differing_valid_proofs: u64,
+}
+
+fn format_counts<K: fmt::Display>(counts: &BTreeMap<K, u64>) -> String {
+    counts
+        .iter()
+        .map(|(key, number)| format!("{}: {}", key, number))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 struct TestingResults(Mutex<HashMap<String, TestingResult>>);
@@ -63,6 +120,31 @@ impl TestingResults {
             .await;
     }
 
+    async fn inc_proof_statuses(
+        &self,
+        method: &str,
+        reference: Option<ProofStatus>,
+        testing: Option<ProofStatus>,
+        comparison: Option<ProofComparison>,
+        responses_differ: bool,
+    ) {
+        self.modify_result(method, |res| {
+            if responses_differ && testing.is_some_and(|status| status.is_valid()) {
+                res.differing_valid_proofs += 1;
+            }
+            if let Some(status) = reference {
+                *res.reference_proofs.entry(status).or_insert(0) += 1;
+            }
+            if let Some(status) = testing {
+                *res.testing_proofs.entry(status).or_insert(0) += 1;
+            }
+            if let Some(comparison) = comparison {
+                *res.proof_comparisons.entry(comparison).or_insert(0) += 1;
+            }
+        })
+        .await;
+    }
+
     async fn modify_result<F>(&self, method: &str, mut f: F)
     where
         F: FnMut(&mut TestingResult),
@@ -75,9 +157,27 @@ impl TestingResults {
     }
 }
 
+/// Splits the response of a batch proof method into a `getAssetProof`-like
+/// response for each of `ids`, whether the batch is a map from id to proof
+/// or a list of proofs in the order of the ids
+fn split_proof_batch(response: &Value, ids: &[String]) -> Vec<Value> {
+    let result = &response["result"];
+    ids.iter()
+        .enumerate()
+        .map(|(position, id)| {
+            let proof = match result {
+                Value::Array(proofs) => proofs.get(position).cloned().unwrap_or_default(),
+                _ => result[id.as_str()].clone(),
+            };
+            json!({ "result": proof })
+        })
+        .collect()
+}
+
 #[derive(Default)]
 struct DiffWithResponses {
     diff: Option<String>,
+    reference_response: Value,
     testing_response: Value,
 }
 
@@ -89,7 +189,7 @@ where
     testing_host: String,
     api: IntegrityVerificationApi,
     keys_fetcher: T,
-    rpc_client: RpcClient,
+    proof_validator: ProofValidator,
     regexes: Vec<Regex>,
     test_retries: u64,
     test_results: TestingResults,
@@ -116,7 +216,7 @@ where
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(Self {
-            rpc_client: RpcClient::new(config.rpc_endpoint.clone()),
+            proof_validator: ProofValidator::new(config),
             reference_host: config.reference_host.clone(),
             testing_host: config.testing_host.clone(),
             api: IntegrityVerificationApi::new(),
@@ -134,6 +234,30 @@ where
                 "RESULTS OF {} METHOD TEST: TESTED PUBKEYS TOTAL: {}, FAILED TESTS: {}",
                 method, result.total_tests, result.failed_tests
             );
+            if !result.reference_proofs.is_empty() || !result.testing_proofs.is_empty() {
+                info!(
+                    "PROOFS OF {} METHOD TEST: REFERENCE: {}; TESTING: {}; COMPARED: {}; DIFFERING BUT TESTING VALID: {}",
+                    method,
+                    format_counts(&result.reference_proofs),
+                    format_counts(&result.testing_proofs),
+                    format_counts(&result.proof_comparisons),
+                    result.differing_valid_proofs
+                );
+            }
+        }
+        let account_fetches = self.proof_validator.account_fetches();
+        if !account_fetches.is_empty() {
+            info!(
+                "ACCOUNT FETCHES OF PROOF CHECKS: {}",
+                account_fetches
+                    .iter()
+                    .map(|(endpoint, fetches)| format!(
+                        "{}: {} succeeded, {} failed",
+                        endpoint, fetches.succeeded, fetches.failed
+                    ))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
         }
     }
 }
@@ -190,6 +314,7 @@ where
 
         DiffWithResponses {
             diff: self.compare_responses(&reference_response, &testing_response),
+            reference_response,
             testing_response,
         }
     }
@@ -208,7 +333,7 @@ where
             }
 
             let mut test_failed = false;
-            if let Some(diff) = diff_with_responses.diff {
+            if let Some(diff) = &diff_with_responses.diff {
                 test_failed = true;
                 if self.log_differences {
                     error!(
@@ -219,22 +344,14 @@ where
             }
 
             if req.method == GET_ASSET_PROOF_METHOD {
-                let asset_id = req.params["id"].as_str().unwrap_or_default();
-                test_failed = match self
-                    .check_proof_valid(asset_id, diff_with_responses.testing_response)
+                test_failed = self
+                    .check_proofs(
+                        &req.method,
+                        req.params["id"].as_str().unwrap_or_default(),
+                        &diff_with_responses,
+                    )
                     .await
-                {
-                    Ok(proof_valid) => {
-                        if !proof_valid {
-                            error!("Invalid proof for {} asset", asset_id)
-                        };
-                        !proof_valid
-                    }
-                    Err(e) => {
-                        error!("Check proof valid: {}", e);
-                        test_failed
-                    }
-                };
+                    .unwrap_or(test_failed);
             }
             if test_failed {
                 self.test_results.inc_failed_tests(&req.method).await;
@@ -245,6 +362,214 @@ where
         }
     }
 
+    /// Validates the proof of each host with the `getAsset` of the same host.
+    /// Returns whether the test failed, `None` if the testing proof could not
+    /// be checked and the diff decides
+    async fn check_proofs(
+        &self,
+        method: &str,
+        asset_id: &str,
+        responses: &DiffWithResponses,
+    ) -> Option<bool> {
+        let (reference, testing) = tokio::join!(
+            self.proof_validator.check_proof_valid(
+                &self.reference_host,
+                asset_id,
+                &responses.reference_response
+            ),
+            self.proof_validator.check_proof_valid(
+                &self.testing_host,
+                asset_id,
+                &responses.testing_response
+            )
+        );
+        let reference = reference
+            .map_err(|e| error!("Check reference proof of {} asset: {}", asset_id, e))
+            .ok();
+        let testing = testing
+            .map_err(|e| error!("Check testing proof of {} asset: {}", asset_id, e))
+            .ok();
+
+        let comparison = reference.zip(testing).map(|(reference, testing)| {
+            ProofComparison::new(reference, testing, responses.diff.is_some())
+        });
+        if let Some(status) = testing.filter(|status| !status.is_valid()) {
+            error!(
+                "Testing host returned {} proof for {} asset",
+                status, asset_id
+            );
+        }
+        if let Some(status) = reference.filter(|status| !status.is_valid()) {
+            error!(
+                "Reference host returned {} proof for {} asset",
+                status, asset_id
+            );
+        }
+        if comparison == Some(ProofComparison::BothValidDifferent) {
+            info!(
+                "Both hosts returned valid but different proofs for {} asset",
+                asset_id
+            );
+        } else if responses.diff.is_some() && testing.is_some_and(|status| status.is_valid()) {
+            // The valid proof passes the test, the mismatch is still reported
+            warn!(
+                "Testing host returned a valid proof differing from the reference response for {} asset",
+                asset_id
+            );
+        }
+        self.test_results
+            .inc_proof_statuses(
+                method,
+                reference,
+                testing,
+                comparison,
+                responses.diff.is_some(),
+            )
+            .await;
+
+        testing.map(|status| !status.is_valid())
+    }
+
+    /// Returns whether the proof the testing host returns in a batch disagrees
+    /// with the one its `getAssetProof` returns: another leaf, tree or number
+    /// of nodes, or a single proof that is not valid. The root and the nodes
+    /// may change with writes to the tree between both requests. A single
+    /// proof that cannot be fetched counts as disagreeing
+    async fn differs_from_single_proof(
+        &self,
+        method: &str,
+        asset_id: &str,
+        batched: &Value,
+    ) -> bool {
+        let request = json!(Body::new(
+            GET_ASSET_PROOF_METHOD,
+            json!(generate_get_asset_proof_params(asset_id.to_string()))
+        ))
+        .to_string();
+        let mut single = None;
+        for _ in 0..self.test_retries {
+            match self.api.make_request(&self.testing_host, &request).await {
+                Ok(response) => {
+                    single = Some(response);
+                    break;
+                }
+                Err(e) => error!("Testing host network error: {}", e),
+            }
+            // Prevent rate-limit errors
+            tokio::time::sleep(Duration::from_millis(REQUESTS_INTERVAL_MILLIS)).await;
+        }
+        let Some(single) = single else {
+            error!(
+                "{}: getAssetProof of {} asset could not be fetched",
+                method, asset_id
+            );
+            return true;
+        };
+
+        let (batched_proof, single_proof) = (&batched["result"], &single["result"]);
+        let node_count = |proof: &Value| proof["proof"].as_array().map(Vec::len);
+        if batched_proof["leaf"] != single_proof["leaf"]
+            || batched_proof["tree_id"] != single_proof["tree_id"]
+            || node_count(batched_proof) != node_count(single_proof)
+        {
+            error!(
+                "{}: proof of {} asset differs from its getAssetProof",
+                method, asset_id
+            );
+            if self.log_differences {
+                error!(
+                    "{}: batched: {}, single: {}",
+                    method, batched_proof, single_proof
+                );
+            }
+            return true;
+        }
+
+        match self
+            .proof_validator
+            .check_proof_valid(&self.testing_host, asset_id, &single)
+            .await
+        {
+            Ok(status) if !status.is_valid() => {
+                error!(
+                    "{}: getAssetProof of {} asset is {}",
+                    method, asset_id, status
+                );
+                true
+            }
+            Ok(_) => false,
+            // Like for the batched proofs, a proof that cannot be checked does
+            // not fail the test
+            Err(e) => {
+                error!("Check single proof of {} asset: {}", asset_id, e);
+                false
+            }
+        }
+    }
+
+    /// Checks every batch of ids with `method`: the batch against the one of
+    /// the reference host, then every proof of the batch against the one of
+    /// the reference batch, the `getAssetProof` of the testing host and the
+    /// tree
+    async fn check_proof_batches(&self, method: &str, batches: Vec<Vec<String>>) {
+        for ids in batches.into_iter() {
+            let req = Body::new(method, json!(generate_get_asset_proofs_params(ids.clone())));
+            self.test_results.inc_total_tests(method).await;
+            let mut batch = DiffWithResponses::default();
+            for _ in 0..self.test_retries {
+                batch = self.check_request(&req).await;
+                if batch.diff.is_none() {
+                    break;
+                }
+                // Prevent rate-limit errors
+                tokio::time::sleep(Duration::from_millis(REQUESTS_INTERVAL_MILLIS)).await;
+            }
+            if let (Some(diff), true) = (&batch.diff, self.log_differences) {
+                error!(
+                    "{}: mismatch responses: req: {:#?}, diff: {}",
+                    method, req, diff
+                );
+            }
+
+            // Proofs decide the test like for `getAssetProof`, the diff of the
+            // batch only where a proof could not be checked
+            let mut test_failed = false;
+            let reference_proofs = split_proof_batch(&batch.reference_response, &ids);
+            let testing_proofs = split_proof_batch(&batch.testing_response, &ids);
+            for ((asset_id, reference_response), testing_response) in
+                ids.iter().zip(reference_proofs).zip(testing_proofs)
+            {
+                // Every id sends a single proof and the assets of both hosts,
+                // prevent rate-limit errors like between single requests
+                tokio::time::sleep(Duration::from_millis(REQUESTS_INTERVAL_MILLIS)).await;
+                let responses = DiffWithResponses {
+                    diff: self.compare_responses(&reference_response, &testing_response),
+                    reference_response,
+                    testing_response,
+                };
+                if self
+                    .differs_from_single_proof(method, asset_id, &responses.testing_response)
+                    .await
+                {
+                    test_failed = true;
+                }
+                if self
+                    .check_proofs(method, asset_id, &responses)
+                    .await
+                    .unwrap_or(responses.diff.is_some())
+                {
+                    test_failed = true;
+                }
+            }
+            if test_failed {
+                self.test_results.inc_failed_tests(method).await;
+            }
+
+            // Prevent rate-limit errors
+            tokio::time::sleep(Duration::from_millis(REQUESTS_INTERVAL_MILLIS)).await;
+        }
+    }
+
     pub async fn check_get_asset(&self) -> Result<(), IntegrityVerificationError> {
         let verification_required_keys = self
             .keys_fetcher
@@ -284,6 +609,32 @@ where
         Ok(())
     }
 
+    pub async fn check_get_asset_proofs(&self) -> Result<(), IntegrityVerificationError> {
+        let batches = self
+            .keys_fetcher
+            .get_verification_required_asset_proofs_batches()
+            .await
+            .map_err(IntegrityVerificationError::FetchKeys)?;
+
+        self.check_proof_batches(GET_ASSET_PROOFS_METHOD, batches)
+            .await;
+
+        Ok(())
+    }
+
+    pub async fn check_get_asset_proof_batch(&self) -> Result<(), IntegrityVerificationError> {
+        let batches = self
+            .keys_fetcher
+            .get_verification_required_asset_proof_batch_batches()
+            .await
+            .map_err(IntegrityVerificationError::FetchKeys)?;
+
+        self.check_proof_batches(GET_ASSET_PROOF_BATCH_METHOD, batches)
+            .await;
+
+        Ok(())
+    }
+
     pub async fn check_get_asset_by_authority(&self) -> Result<(), IntegrityVerificationError> {
         let verification_required_keys = self
             .keys_fetcher
@@ -296,7 +647,13 @@ where
             .map(|key| {
                 Body::new(
                     GET_ASSET_BY_AUTHORITY_METHOD,
-                    json!(generate_get_assets_by_authority_params(key, None, None)),
+                    json!(generate_get_assets_by_authority_params(
+                        key,
+                        None,
+                        None,
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -318,7 +675,13 @@ where
             .map(|key| {
                 Body::new(
                     GET_ASSET_BY_OWNER_METHOD,
-                    json!(generate_get_assets_by_owner_params(key, None, None)),
+                    json!(generate_get_assets_by_owner_params(
+                        key,
+                        None,
+                        None,
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -340,7 +703,13 @@ where
             .map(|key| {
                 Body::new(
                     GET_ASSET_BY_GROUP_METHOD,
-                    json!(generate_get_assets_by_group_params(key, None, None)),
+                    json!(generate_get_assets_by_group_params(
+                        key,
+                        None,
+                        None,
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -362,7 +731,13 @@ where
             .map(|key| {
                 Body::new(
                     GET_ASSET_BY_CREATOR_METHOD,
-                    json!(generate_get_assets_by_creator_params(key, None, None)),
+                    json!(generate_get_assets_by_creator_params(
+                        key,
+                        None,
+                        None,
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -372,74 +747,6 @@ where
         Ok(())
     }
 
-    async fn check_proof_valid(
-        &self,
-        asset_id: &str,
-        response: Value,
-    ) -> Result<bool, IntegrityVerificationError> {
-        let tree_id = response["result"]["tree_id"].as_str().ok_or(
-            IntegrityVerificationError::CannotGetResponseField("tree_id".to_string()),
-        )?;
-        let leaf = Pubkey::from_str(response["result"]["leaf"].as_str().ok_or(
-            IntegrityVerificationError::CannotGetResponseField("leaf".to_string()),
-        )?)?
-        .to_bytes();
-
-        let get_asset_req = json!(&Body::new(
-            GET_ASSET_METHOD,
-            json!(generate_get_asset_params(asset_id.to_string()))
-        ))
-        .to_string();
-        let get_asset_fut = self.api.make_request(&self.reference_host, &get_asset_req);
-        let tree_id_pk = Pubkey::from_str(tree_id)?;
-        let get_account_data_fut = self.rpc_client.get_account_with_commitment(
-            &tree_id_pk,
-            CommitmentConfig {
-                commitment: CommitmentLevel::Processed,
-            },
-        );
-        let (get_asset, account_data) = tokio::join!(get_asset_fut, get_account_data_fut);
-        let get_asset = get_asset?;
-        let leaf_index = get_asset["result"]["compression"]["leaf_id"]
-            .as_u64()
-            .ok_or(IntegrityVerificationError::CannotGetResponseField(
-                "leaf_id".to_string(),
-            ))? as u32;
-        let mut tree_acc_info = account_data?
-            .value
-            .ok_or(IntegrityVerificationError::NullAssetAccount(
-                tree_id_pk.to_string(),
-            ))?
-            .data;
-
-        let (header_bytes, rest) =
-            tree_acc_info.split_at_mut(CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1);
-        let header = ConcurrentMerkleTreeHeader::try_from_slice(header_bytes)?;
-        let merkle_tree_size = merkle_tree_get_size(&header)?;
-        let (tree_bytes, canopy_bytes) = rest.split_at_mut(merkle_tree_size);
-
-        let mut initial_proofs = response["result"]["proof"]
-            .as_array()
-            .ok_or(IntegrityVerificationError::CannotGetResponseField(
-                "proof".to_string(),
-            ))?
-            .iter()
-            .filter_map(|proof| {
-                proof
-                    .as_str()
-                    .and_then(|v| Pubkey::from_str(v).ok().map(|p| p.to_bytes()))
-            })
-            .collect::<Vec<_>>();
-        fill_in_proof_from_canopy(
-            canopy_bytes,
-            header.get_max_depth(),
-            leaf_index,
-            &mut initial_proofs,
-        )?;
-
-        check_proof!(&header, &tree_bytes, initial_proofs, leaf, leaf_index)
-    }
-
     pub async fn check_get_token_accounts_by_owner(
         &self,
     ) -> Result<(), IntegrityVerificationError> {
@@ -454,7 +761,12 @@ where
             .map(|owner| {
                 Body::new(
                     GET_TOKEN_ACCOUNTS,
-                    json!(generate_get_token_accounts(Some(owner), None)),
+                    json!(generate_get_token_accounts(
+                        Some(owner),
+                        None,
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -476,7 +788,12 @@ where
             .map(|mint| {
                 Body::new(
                     GET_TOKEN_ACCOUNTS,
-                    json!(generate_get_token_accounts(None, Some(mint))),
+                    json!(generate_get_token_accounts(
+                        None,
+                        Some(mint),
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -500,7 +817,12 @@ where
             .map(|pair| {
                 Body::new(
                     GET_TOKEN_ACCOUNTS,
-                    json!(generate_get_token_accounts(Some(pair.0), Some(pair.1))),
+                    json!(generate_get_token_accounts(
+                        Some(pair.0),
+                        Some(pair.1),
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -522,7 +844,11 @@ where
             .map(|asset| {
                 Body::new(
                     GET_SIGNATURES_FOR_ASSET,
-                    json!(generate_get_signatures_for_asset(asset)),
+                    json!(generate_get_signatures_for_asset(
+                        asset,
+                        &ParamsDistribution::default(),
+                        &mut rand::thread_rng()
+                    )),
                 )
             })
             .collect::<Vec<_>>();
@@ -535,6 +861,8 @@ where
 
 #[cfg(test)]
 mod tests {
+    use super::{split_proof_batch, TestingResults};
+    use crate::proof_validator::ProofStatus;
     use assert_json_diff::{assert_json_matches_no_panic, CompareMode, Config};
     use regex::Regex;
     use serde_json::json;
@@ -626,4 +954,87 @@ mod tests {
             res.trim()
         );
     }
+
+    #[test]
+    fn test_proof_comparison() {
+        use super::ProofComparison;
+        use crate::proof_validator::ProofStatus;
+
+        assert_eq!(
+            ProofComparison::BothValidSame,
+            ProofComparison::new(ProofStatus::ValidCurrent, ProofStatus::ValidCurrent, false)
+        );
+        assert_eq!(
+            ProofComparison::BothValidDifferent,
+            ProofComparison::new(ProofStatus::ValidCurrent, ProofStatus::ValidRecent, true)
+        );
+        assert_eq!(
+            ProofComparison::TestingInvalid,
+            ProofComparison::new(ProofStatus::ValidCurrent, ProofStatus::Stale, true)
+        );
+        assert_eq!(
+            ProofComparison::ReferenceInvalid,
+            ProofComparison::new(ProofStatus::LeafMismatch, ProofStatus::ValidRecent, true)
+        );
+        assert_eq!(
+            ProofComparison::BothInvalid,
+            ProofComparison::new(ProofStatus::Inconsistent, ProofStatus::Stale, false)
+        );
+    }
+
+    #[test]
+    fn test_split_proof_batch() {
+        let ids = vec!["first".to_string(), "second".to_string()];
+        let by_id = json!({"result": {"second": {"leaf": "2"}, "first": {"leaf": "1"}}});
+        assert_eq!(
+            vec![
+                json!({"result": {"leaf": "1"}}),
+                json!({"result": {"leaf": "2"}})
+            ],
+            split_proof_batch(&by_id, &ids)
+        );
+
+        let in_order = json!({"result": [{"leaf": "1"}]});
+        assert_eq!(
+            vec![json!({"result": {"leaf": "1"}}), json!({"result": null})],
+            split_proof_batch(&in_order, &ids)
+        );
+
+        let error = json!({"error": {"code": -32601}});
+        assert_eq!(
+            vec![json!({"result": null}), json!({"result": null})],
+            split_proof_batch(&error, &ids)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_differing_valid_proofs_counted() {
+        let results = TestingResults::new();
+        // The reference proof could not be checked, nothing is compared
+        results
+            .inc_proof_statuses(
+                "getAssetProof",
+                None,
+                Some(ProofStatus::ValidCurrent),
+                None,
+                true,
+            )
+            .await;
+        results
+            .inc_proof_statuses(
+                "getAssetProof",
+                None,
+                Some(ProofStatus::ValidCurrent),
+                None,
+                false,
+            )
+            .await;
+        results
+            .inc_proof_statuses("getAssetProof", None, Some(ProofStatus::Stale), None, true)
+            .await;
+
+        let results = results.0.lock().await;
+        assert_eq!(1, results["getAssetProof"].differing_valid_proofs);
+        assert!(results["getAssetProof"].proof_comparisons.is_empty());
+    }
 }
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::IntegrityVerificationConfig,
+    error::IntegrityVerificationError,
+    file_keys_fetcher::FileKeysFetcher,
+    performance_measurement::{
+        report_performance_results, Commands, LoadProfile, LoadStage, Stats,
+    },
+};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// Messages sent by the controller to its agents
+#[derive(Serialize, Deserialize)]
+pub enum ControllerMessage {
+    // Everything an agent needs to generate the same load as a local run,
+    // accepted only with the token the agent was started with
+    Setup {
+        token: String,
+        config: Box<IntegrityVerificationConfig>,
+        keys: HashMap<String, Vec<String>>,
+        // Position of the agent among the agents of the run, which decides
+        // its share of the keys picked sequentially
+        agent_index: usize,
+        num_of_agents: usize,
+    },
+    // Start runs the given workers on the agent, Stop ends the stage
+    Command(Commands),
+}
+
+/// Messages sent by agents back to the controller
+#[derive(Serialize, Deserialize)]
+pub enum AgentMessage {
+    Ready,
+    Stats(Box<Stats>),
+    Error(String),
+}
+
+/// Connection exchanging messages as JSON documents, one per line
+struct Connection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    async fn send<T: serde::Serialize>(
+        &mut self,
+        message: &T,
+    ) -> Result<(), IntegrityVerificationError> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Returns `None` once the other side closed the connection
+    async fn receive<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<Option<T>, IntegrityVerificationError> {
+        match self.lines.next_line().await? {
+            Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Compares tokens in a time that does not depend on where they differ
+fn tokens_match(received: &str, expected: &str) -> bool {
+    received.len() == expected.len()
+        && received
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (r, e)| diff | (r ^ e))
+            == 0
+}
+
+/// Runs the stages the controllers connected to `listen_address` ask for, one
+/// controller at a time. Controllers have to present `token` first
+pub async fn run_agent(
+    listen_address: &str,
+    token: &str,
+) -> Result<(), IntegrityVerificationError> {
+    let listener = TcpListener::bind(listen_address).await?;
+    info!("Agent is listening on {}", listener.local_addr()?);
+    serve_agent(listener, token).await
+}
+
+async fn serve_agent(listener: TcpListener, token: &str) -> Result<(), IntegrityVerificationError> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        info!("Controller {} connected", address);
+        match serve_controller(Connection::new(stream), token).await {
+            Ok(()) => info!("Controller {} disconnected", address),
+            Err(err) => error!("Controller {}: {}", address, err),
+        }
+    }
+}
+
+async fn serve_controller(
+    mut connection: Connection,
+    token: &str,
+) -> Result<(), IntegrityVerificationError> {
+    let mut setup = None;
+    let mut stage = None;
+
+    while let Some(message) = connection.receive::<ControllerMessage>().await? {
+        // Nothing but a setup with the right token is served before a setup
+        // was accepted, the connection is closed instead
+        let authorized = match &message {
+            ControllerMessage::Setup {
+                token: received, ..
+            } => tokens_match(received, token),
+            ControllerMessage::Command(_) => setup.is_some(),
+        };
+        if !authorized {
+            connection
+                .send(&AgentMessage::Error("Unauthorized".to_string()))
+                .await?;
+            return Err(IntegrityVerificationError::Agent(
+                "controller is not authorized".to_string(),
+            ));
+        }
+
+        match message {
+            ControllerMessage::Setup {
+                config,
+                keys,
+                agent_index,
+                num_of_agents,
+                ..
+            } => {
+                let mut keys_fetcher = FileKeysFetcher::from_keys(keys);
+                keys_fetcher.set_key_partition(agent_index, num_of_agents);
+                match LoadProfile::with_keys(&config, keys_fetcher) {
+                    Ok(profile) => {
+                        setup = Some((config.testing_host.clone(), profile));
+                        connection.send(&AgentMessage::Ready).await?;
+                    }
+                    Err(err) => {
+                        connection
+                            .send(&AgentMessage::Error(err.to_string()))
+                            .await?;
+                    }
+                }
+            }
+            ControllerMessage::Command(Commands::Start(ids)) => {
+                if let Some((api_url, profile)) = &setup {
+                    info!("Starting {} workers", ids.len());
+                    stage = Some(LoadStage::start(ids, api_url, profile));
+                }
+            }
+            ControllerMessage::Command(Commands::Stop(_) | Commands::Abort(_)) => {
+                match stage.take() {
+                    Some(stage) => {
+                        let stat = stage.stop().await;
+                        connection
+                            .send(&AgentMessage::Stats(Box::new(stat)))
+                            .await?;
+                    }
+                    None => {
+                        connection
+                            .send(&AgentMessage::Error("Stop without start".to_string()))
+                            .await?;
+                    }
+                }
+            }
+            ControllerMessage::Command(Commands::Init) => {}
+        }
+    }
+
+    if let Some(stage) = stage {
+        warn!("Controller disconnected during the load, stopping workers");
+        stage.stop().await;
+    }
+
+    Ok(())
+}
+
+/// Spreads workers `0..num_of_workers` over the agents round robin
+fn split_workers(num_of_workers: usize, num_of_agents: usize) -> Vec<Vec<u32>> {
+    let mut ids = vec![Vec::new(); num_of_agents];
+    for id in 0..num_of_workers {
+        ids[id % num_of_agents].push(id as u32);
+    }
+    ids
+}
+
+async fn receive_from_agent(
+    address: &str,
+    agent: &mut Connection,
+) -> Result<AgentMessage, IntegrityVerificationError> {
+    match agent.receive().await? {
+        Some(AgentMessage::Error(err)) => Err(IntegrityVerificationError::Agent(format!(
+            "{}: {}",
+            address, err
+        ))),
+        Some(message) => Ok(message),
+        None => Err(IntegrityVerificationError::Agent(format!(
+            "{}: disconnected",
+            address
+        ))),
+    }
+}
+
+/// Runs the performance test on the agents of `config` and reports their
+/// merged statistics as if the load came from a single process
+pub async fn run_distributed_performance_tests(
+    config: &IntegrityVerificationConfig,
+    results_path: Option<&str>,
+    baseline_path: Option<&str>,
+    cancel_token: CancellationToken,
+) -> Result<(), IntegrityVerificationError> {
+    let (profile, stat) = run_on_agents(config, cancel_token).await?;
+    report_performance_results(config, &profile, &stat, results_path, baseline_path)
+}
+
+/// Runs the load of `config` on its agents and merges their statistics
+async fn run_on_agents(
+    config: &IntegrityVerificationConfig,
+    cancel_token: CancellationToken,
+) -> Result<(LoadProfile, Stats), IntegrityVerificationError> {
+    if config.agents.is_empty() {
+        return Err(IntegrityVerificationError::ValidateConfig(
+            "agents".to_string(),
+        ));
+    }
+
+    let profile = LoadProfile::from_config(config).await?;
+    // Agents share the seed, their workers have different ids
+    let mut agent_config = config.clone();
+    agent_config.seed = Some(profile.seed());
+
+    let mut agents = Vec::new();
+    for (agent_index, address) in config.agents.iter().enumerate() {
+        let mut agent = Connection::new(TcpStream::connect(address).await?);
+        agent
+            .send(&ControllerMessage::Setup {
+                token: config.agent_token.clone(),
+                config: Box::new(agent_config.clone()),
+                keys: profile.keys().clone(),
+                agent_index,
+                num_of_agents: config.agents.len(),
+            })
+            .await?;
+        agents.push((address.as_str(), agent));
+    }
+    for (address, agent) in agents.iter_mut() {
+        match receive_from_agent(address, agent).await? {
+            AgentMessage::Ready => info!("Agent {} is ready", address),
+            _ => {
+                return Err(IntegrityVerificationError::Agent(format!(
+                    "{}: unexpected message",
+                    address
+                )))
+            }
+        }
+    }
+
+    let agent_ids = split_workers(config.num_of_virtual_users, agents.len());
+    for ((_, agent), ids) in agents.iter_mut().zip(agent_ids.iter()) {
+        agent
+            .send(&ControllerMessage::Command(Commands::Start(ids.clone())))
+            .await?;
+    }
+    let measured_from = Instant::now() + profile.warm_up();
+
+    let interrupted_after = tokio::select! {
+        _ = tokio::time::sleep_until(
+            measured_from + Duration::from_secs(config.test_duration_time)
+        ) => None,
+        _ = cancel_token.cancelled() => {
+            warn!("Load was interrupted, stopping agents");
+            Some(Instant::now().saturating_duration_since(measured_from))
+        }
+    };
+
+    for ((_, agent), ids) in agents.iter_mut().zip(agent_ids.into_iter()) {
+        agent
+            .send(&ControllerMessage::Command(Commands::Stop(ids)))
+            .await?;
+    }
+    let mut stat = Stats::starting_at(measured_from);
+    for (address, agent) in agents.iter_mut() {
+        match receive_from_agent(address, agent).await? {
+            AgentMessage::Stats(agent_stat) => stat.merge(*agent_stat),
+            _ => {
+                return Err(IntegrityVerificationError::Agent(format!(
+                    "{}: unexpected message",
+                    address
+                )))
+            }
+        }
+    }
+    if let Some(after) = interrupted_after {
+        stat.mark_interrupted(after);
+    }
+
+    Ok((profile, stat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_on_agents, serve_agent, split_workers, tokens_match};
+    use crate::{api::test_json_rpc_server, config::IntegrityVerificationConfig};
+    use serde_json::json;
+    use std::{io::Write, sync::atomic::Ordering, time::Duration};
+    use tokio::net::TcpListener;
+    use tokio_util::sync::CancellationToken;
+
+    #[test]
+    fn test_split_workers() {
+        assert_eq!(
+            vec![vec![0, 3, 6], vec![1, 4], vec![2, 5]],
+            split_workers(7, 3)
+        );
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret1", "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_agents_on_localhost() {
+        let (url, requests) = test_json_rpc_server(
+            Duration::ZERO,
+            |request| json!({"jsonrpc": "2.0", "id": request["id"], "result": {}}),
+        )
+        .await;
+        let mut agents = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            agents.push(listener.local_addr().unwrap().to_string());
+            tokio::spawn(async move { serve_agent(listener, "secret").await });
+        }
+        let mut keys = tempfile::NamedTempFile::new().unwrap();
+        writeln!(keys, "getAsset:\nkey1,key2,key3").unwrap();
+
+        let config = |token: &str| -> IntegrityVerificationConfig {
+            serde_json::from_value(json!({
+                "reference_host": url,
+                "testing_host": url,
+                "testing_file_path": keys.path(),
+                "num_of_virtual_users": 4,
+                "test_duration_time": 1,
+                "agents": agents,
+                "agent_token": token,
+            }))
+            .unwrap()
+        };
+
+        let (_, stat) = run_on_agents(&config("secret"), CancellationToken::new())
+            .await
+            .unwrap();
+        let sent = requests.load(Ordering::Relaxed);
+        assert!(sent > 0);
+        assert_eq!(sent, stat.overall().total_requests());
+
+        assert!(run_on_agents(&config("wrong"), CancellationToken::new())
+            .await
+            .is_err());
+    }
+}
@@ -30,4 +30,10 @@ pub enum IntegrityVerificationError {
     ValidateConfig(String),
     #[error("ResponseStatusCode: {0}")]
     ResponseStatusCode(u16),
+    #[error("BaselineRegression: {0}")]
+    BaselineRegression(String),
+    #[error("Agent {0}")]
+    Agent(String),
+    #[error("Corpus {0}")]
+    Corpus(String),
 }
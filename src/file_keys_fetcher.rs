@@ -1,20 +1,74 @@
 use crate::diff_checker::{
     GET_ASSET_BY_AUTHORITY_METHOD, GET_ASSET_BY_CREATOR_METHOD, GET_ASSET_BY_GROUP_METHOD,
-    GET_ASSET_BY_OWNER_METHOD, GET_ASSET_METHOD, GET_ASSET_PROOF_METHOD, GET_SIGNATURES_FOR_ASSET,
+    GET_ASSET_BY_OWNER_METHOD, GET_ASSET_METHOD, GET_ASSET_PROOFS_METHOD,
+    GET_ASSET_PROOF_BATCH_METHOD, GET_ASSET_PROOF_METHOD, GET_SIGNATURES_FOR_ASSET,
     GET_TOKEN_ACCOUNTS_BY_MINT, GET_TOKEN_ACCOUNTS_BY_OWNER, GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT,
 };
 use crate::interfaces::IntegrityVerificationKeysFetcher;
 use async_trait::async_trait;
+use rand::distributions::WeightedIndex;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::warn;
+
+/// Splits a key of the batch methods, written as `(id1;id2;id3)`, into the
+/// ids of the batch
+pub fn parse_key_batch(key: &str) -> Vec<String> {
+    key.trim_matches(|c| c == '(' || c == ')')
+        .split(';')
+        .filter(|id| !id.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// How keys of a method are picked, which decides how often the tested API
+/// hits its caches
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySelection {
+    #[default]
+    Uniform,
+    // Every key is requested once before any key repeats
+    SequentialUnique,
+    // A few keys get most of the requests, the earlier a key is in the keys
+    // file the hotter it is
+    Zipfian,
+}
+
+impl fmt::Display for KeySelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let selection = match self {
+            KeySelection::Uniform => "uniform",
+            KeySelection::SequentialUnique => "sequential unique",
+            KeySelection::Zipfian => "zipfian",
+        };
+        write!(f, "{}", selection)
+    }
+}
 
 #[derive(Clone)]
 pub struct FileKeysFetcher {
     pub keys_map: HashMap<String, Vec<String>>,
+    // Methods of `keys_map` in a fixed order, so a seed picks the same ones
+    methods: Vec<String>,
     rnd: StdRng,
+    // Methods with their weights, used instead of the uniform choice when set
+    method_weights: Option<(Vec<String>, WeightedIndex<u32>)>,
+    key_selection: KeySelection,
+    // Next key of every method, shared by forks so they never pick the same key
+    key_cursors: Arc<HashMap<String, AtomicUsize>>,
+    // Index of this process among the processes sharing the keys and their
+    // number, sequential selection only picks keys of this process's share
+    key_partition: (usize, usize),
+    // Popularity of every key of a method for the Zipfian selection
+    zipf_indexes: HashMap<String, WeightedIndex<f64>>,
 }
 
 impl FileKeysFetcher {
@@ -44,26 +98,153 @@ impl FileKeysFetcher {
             }
         }
 
-        let rnd = StdRng::from_entropy();
+        Ok(Self::from_keys(keys_map))
+    }
+
+    pub fn from_keys(keys_map: HashMap<String, Vec<String>>) -> Self {
+        let mut methods: Vec<String> = keys_map.keys().cloned().collect();
+        methods.sort();
+        FileKeysFetcher {
+            keys_map,
+            methods,
+            rnd: StdRng::from_entropy(),
+            method_weights: None,
+            key_selection: KeySelection::default(),
+            key_cursors: Arc::new(HashMap::new()),
+            key_partition: (0, 1),
+            zipf_indexes: HashMap::new(),
+        }
+    }
+
+    /// Makes `get_random_command` pick keys with `selection`. With Zipfian
+    /// selection the key of rank `n` is picked proportionally to
+    /// `1 / n^zipf_exponent`
+    pub fn set_key_selection(
+        &mut self,
+        selection: KeySelection,
+        zipf_exponent: f64,
+    ) -> Result<(), String> {
+        self.key_selection = selection;
+        self.key_cursors = Arc::new(
+            self.keys_map
+                .keys()
+                .map(|method| (method.clone(), AtomicUsize::new(0)))
+                .collect(),
+        );
+        self.zipf_indexes = HashMap::new();
+        if selection == KeySelection::Zipfian {
+            for (method, keys) in self.keys_map.iter() {
+                let weights = (1..=keys.len()).map(|rank| 1.0 / (rank as f64).powf(zipf_exponent));
+                let index = WeightedIndex::new(weights).map_err(|e| e.to_string())?;
+                self.zipf_indexes.insert(method.clone(), index);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes the sequential selection pick only every `count`-th key starting
+    /// at `index`, so processes with different `index` never pick the same key
+    pub fn set_key_partition(&mut self, index: usize, count: usize) {
+        self.key_partition = (index, count.max(1));
+    }
+
+    pub fn key_selection(&self) -> KeySelection {
+        self.key_selection
+    }
+
+    /// Makes `get_random_command` pick methods proportionally to `weights`.
+    /// Methods missing from `weights` are never picked
+    pub fn set_method_weights(&mut self, weights: &HashMap<String, u32>) -> Result<(), String> {
+        let mut methods = Vec::new();
+        let mut method_weights = Vec::new();
+        let mut weights: Vec<_> = weights.iter().collect();
+        weights.sort();
+        for (method, weight) in weights {
+            if !self.keys_map.contains_key(method) {
+                return Err(format!("No keys for weighted method {}", method));
+            }
+            methods.push(method.clone());
+            method_weights.push(*weight);
+        }
+        let index = WeightedIndex::new(method_weights).map_err(|e| e.to_string())?;
+        self.method_weights = Some((methods, index));
 
-        Ok(FileKeysFetcher { keys_map, rnd })
+        Ok(())
     }
+
+    /// Returns a copy of the fetcher with its own random generator seeded with
+    /// `seed`, so workers sharing the same keys do not send identical request
+    /// sequences while runs stay reproducible
+    pub fn fork(&self, seed: u64) -> Self {
+        Self {
+            rnd: StdRng::seed_from_u64(seed),
+            ..self.clone()
+        }
+    }
+
+    /// Random generator of the fetcher, for choices that have to be
+    /// reproducible with the same seed as the keys
+    pub fn rnd(&mut self) -> &mut StdRng {
+        &mut self.rnd
+    }
+
     fn read_keys(&self, method_name: &str) -> Result<Vec<String>, String> {
         Ok(self.keys_map.get(method_name).cloned().unwrap_or_default())
     }
 
+    fn read_key_batches(&self, method_name: &str) -> Result<Vec<Vec<String>>, String> {
+        Ok(self
+            .read_keys(method_name)?
+            .iter()
+            .map(|key| parse_key_batch(key))
+            .collect())
+    }
+
     pub fn get_random_command(&mut self) -> (String, String) {
-        let commands: Vec<&String> = self.keys_map.keys().collect();
+        let command = match &self.method_weights {
+            Some((methods, index)) => methods[self.rnd.sample(index)].clone(),
+            None => {
+                let command_ind = self.rnd.gen_range(0..self.methods.len());
+
+                self.methods[command_ind].clone()
+            }
+        };
 
-        let command_ind = self.rnd.gen_range(0..commands.len());
+        let arg = self.get_key(&command).unwrap();
 
-        let command_args_len = self.keys_map.get(commands[command_ind]).unwrap().len();
+        (command, arg)
+    }
 
-        let arg_ind = self.rnd.gen_range(0..command_args_len);
+    /// Picks a key of `method` with the configured key selection, `None` if
+    /// the method has no keys
+    pub fn get_key(&mut self, method: &str) -> Option<String> {
+        let command_args_len = self.keys_map.get(method)?.len();
+        if command_args_len == 0 {
+            return None;
+        }
 
-        let arg = self.keys_map.get(commands[command_ind]).unwrap()[arg_ind].clone();
+        let arg_ind = match self.key_selection {
+            KeySelection::Uniform => self.rnd.gen_range(0..command_args_len),
+            KeySelection::SequentialUnique => {
+                let (index, count) = self.key_partition;
+                let cursor = self.key_cursors[method].fetch_add(1, Ordering::Relaxed);
+                let share = command_args_len.saturating_sub(index).div_ceil(count);
+                if cursor == share {
+                    warn!("All {} keys are used, keys repeat from now", method);
+                }
+                // Repeated keys stay within the share, the keys of other
+                // processes must not be warmed up. A process without a share
+                // of keys picks any
+                match share {
+                    0 => cursor % command_args_len,
+                    share => index + (cursor % share) * count,
+                }
+            }
+            KeySelection::Zipfian => self.rnd.sample(&self.zipf_indexes[method]),
+        };
 
-        (commands[command_ind].clone(), arg)
+        Some(self.keys_map[method][arg_ind].clone())
     }
 }
 #[async_trait]
@@ -92,6 +273,18 @@ impl IntegrityVerificationKeysFetcher for FileKeysFetcher {
         self.read_keys(GET_ASSET_PROOF_METHOD)
     }
 
+    async fn get_verification_required_asset_proofs_batches(
+        &self,
+    ) -> Result<Vec<Vec<String>>, String> {
+        self.read_key_batches(GET_ASSET_PROOFS_METHOD)
+    }
+
+    async fn get_verification_required_asset_proof_batch_batches(
+        &self,
+    ) -> Result<Vec<Vec<String>>, String> {
+        self.read_key_batches(GET_ASSET_PROOF_BATCH_METHOD)
+    }
+
     async fn get_verification_required_tokens_by_owner(&self) -> Result<Vec<String>, String> {
         self.read_keys(GET_TOKEN_ACCOUNTS_BY_OWNER)
     }
@@ -124,3 +317,129 @@ impl IntegrityVerificationKeysFetcher for FileKeysFetcher {
         self.read_keys(GET_SIGNATURES_FOR_ASSET)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{FileKeysFetcher, KeySelection};
+    use std::collections::{HashMap, HashSet};
+
+    fn fetcher(keys: usize) -> FileKeysFetcher {
+        FileKeysFetcher::from_keys(HashMap::from([(
+            "getAsset".to_string(),
+            (0..keys).map(|key| key.to_string()).collect(),
+        )]))
+        .fork(0)
+    }
+
+    #[test]
+    fn test_key_selection() {
+        let mut unique = fetcher(100);
+        unique
+            .set_key_selection(KeySelection::SequentialUnique, 1.0)
+            .unwrap();
+        let mut fork = unique.fork(1);
+        let keys: HashSet<String> = (0..50)
+            .flat_map(|_| [unique.get_random_command().1, fork.get_random_command().1])
+            .collect();
+        assert_eq!(100, keys.len());
+
+        // Processes with their own cursors split the keys between them
+        let partitioned: HashSet<String> = (0..2)
+            .flat_map(|index| {
+                let mut fetcher = fetcher(100);
+                fetcher
+                    .set_key_selection(KeySelection::SequentialUnique, 1.0)
+                    .unwrap();
+                fetcher.set_key_partition(index, 2);
+                (0..50).map(move |_| fetcher.get_random_command().1)
+            })
+            .collect();
+        assert_eq!(100, partitioned.len());
+
+        // Once its share is used up a process repeats only its own keys
+        let mut third = fetcher(10);
+        third
+            .set_key_selection(KeySelection::SequentialUnique, 1.0)
+            .unwrap();
+        third.set_key_partition(2, 3);
+        let keys: Vec<String> = (0..7).map(|_| third.get_random_command().1).collect();
+        assert_eq!(vec!["2", "5", "8", "2", "5", "8", "2"], keys);
+
+        let mut zipfian = fetcher(1000);
+        zipfian
+            .set_key_selection(KeySelection::Zipfian, 1.0)
+            .unwrap();
+        let hottest = (0..1000)
+            .filter(|_| zipfian.get_random_command().1 == "0")
+            .count();
+        // The hottest of 1000 keys gets about 13% of requests with exponent 1
+        assert!((80..200).contains(&hottest), "{}", hottest);
+    }
+
+    #[test]
+    fn test_seeded_commands_repeat() {
+        // Every fetcher gets its own map, with its own iteration order
+        let commands = |weighted: bool| {
+            let mut fetcher = FileKeysFetcher::from_keys(
+                [
+                    "getAsset",
+                    "getAssetProof",
+                    "getAssetsByOwner",
+                    "getSignaturesForAsset",
+                ]
+                .into_iter()
+                .map(|method| (method.to_string(), vec![method.to_lowercase()]))
+                .collect(),
+            );
+            if weighted {
+                fetcher
+                    .set_method_weights(&HashMap::from([
+                        ("getAsset".to_string(), 1),
+                        ("getAssetProof".to_string(), 2),
+                        ("getAssetsByOwner".to_string(), 3),
+                    ]))
+                    .unwrap();
+            }
+            let mut fork = fetcher.fork(7);
+            (0..20)
+                .map(|_| fork.get_random_command())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands(false), commands(false));
+        assert_eq!(commands(true), commands(true));
+    }
+
+    #[test]
+    fn test_weighted_method_frequencies() {
+        let mut fetcher = FileKeysFetcher::from_keys(
+            ["getAsset", "getAssetProof", "getAssetsByOwner"]
+                .into_iter()
+                .map(|method| (method.to_string(), vec![method.to_lowercase()]))
+                .collect(),
+        );
+        fetcher
+            .set_method_weights(&HashMap::from([
+                ("getAsset".to_string(), 1),
+                ("getAssetProof".to_string(), 3),
+            ]))
+            .unwrap();
+        let mut fork = fetcher.fork(11);
+
+        let mut frequencies = HashMap::new();
+        for _ in 0..4000 {
+            *frequencies.entry(fork.get_random_command().0).or_insert(0) += 1;
+        }
+        // Expected 1000 and 3000, unweighted methods are never picked
+        assert!(
+            (900..1100).contains(&frequencies["getAsset"]),
+            "{:?}",
+            frequencies
+        );
+        assert!(
+            (2900..3100).contains(&frequencies["getAssetProof"]),
+            "{:?}",
+            frequencies
+        );
+        assert_eq!(None, frequencies.get("getAssetsByOwner"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+// Values below `2^EXACT_BITS` get a bucket each, larger ones share buckets
+// `1 / 2^(EXACT_BITS - 1)` of their value wide
+const EXACT_BITS: u32 = 8;
+
+/// Lowest value of the bucket `value` falls into
+fn bucket(value: u64) -> u64 {
+    let bits = u64::BITS - value.leading_zeros();
+    let shift = bits.saturating_sub(EXACT_BITS);
+    (value >> shift) << shift
+}
+
+/// Highest value of the bucket starting at `bucket`
+fn bucket_end(bucket: u64) -> u64 {
+    let bits = u64::BITS - bucket.leading_zeros();
+    let shift = bits.saturating_sub(EXACT_BITS);
+    bucket + ((1 << shift) - 1)
+}
+
+/// Histogram of values in whatever unit they are recorded in, exact below 256
+/// and within 1% above, so its size only grows with the range of the values.
+/// Histograms collected by different workers are merged without losing
+/// precision
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct Histogram {
+    // Number of values in every bucket, by the lowest value of the bucket
+    counts: BTreeMap<u64, u64>,
+    total: u64,
+    sum: u64,
+    #[serde(default)]
+    min: Option<u64>,
+    #[serde(default)]
+    max: Option<u64>,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        *self.counts.entry(bucket(value)).or_insert(0) += 1;
+        self.total += 1;
+        self.sum += value;
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in other.counts.iter() {
+            *self.counts.entry(*bucket).or_insert(0) += count;
+        }
+        self.total += other.total;
+        self.sum += other.sum;
+        self.min = self.min.into_iter().chain(other.min).min();
+        self.max = self.max.into_iter().chain(other.max).max();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<u64> {
+        self.sum.checked_div(self.total)
+    }
+
+    /// Returns the value below which `percentile` percent of the recorded
+    /// values fall, rounded up to the end of its bucket, or `None` if nothing
+    /// was recorded
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = (((percentile / 100.0) * self.total as f64).ceil() as u64).clamp(1, self.total);
+
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter() {
+            seen += count;
+            if seen >= rank {
+                return Some(bucket_end(*bucket).clamp(self.min?, self.max?));
+            }
+        }
+        self.max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket, Histogram};
+
+    #[test]
+    fn test_merged_percentiles() {
+        let mut first = Histogram::default();
+        let mut second = Histogram::default();
+        for value in 1..=50 {
+            first.record(value);
+            second.record(value + 50);
+        }
+        first.merge(&second);
+
+        assert_eq!(100, first.count());
+        assert_eq!(Some(1), first.min());
+        assert_eq!(Some(100), first.max());
+        assert_eq!(Some(50), first.mean());
+        assert_eq!(Some(50), first.percentile(50.0));
+        assert_eq!(Some(95), first.percentile(95.0));
+        assert_eq!(Some(99), first.percentile(99.0));
+        assert_eq!(None, Histogram::default().percentile(99.0));
+    }
+
+    #[test]
+    fn test_bucketed_values() {
+        assert_eq!(255, bucket(255));
+        assert_eq!(256, bucket(257));
+        assert_eq!(1_000_000 - 1_000_000 % 4096, bucket(1_000_000));
+
+        let mut histogram = Histogram::default();
+        for value in 0..1_000_000 {
+            histogram.record(value);
+        }
+        assert!(histogram.counts.len() < 2000, "{}", histogram.counts.len());
+        assert_eq!(Some(0), histogram.min());
+        assert_eq!(Some(999_999), histogram.max());
+        assert_eq!(Some(499_999), histogram.mean());
+        let p50 = histogram.percentile(50.0).unwrap();
+        assert!((500_000..505_000).contains(&p50), "{}", p50);
+        assert_eq!(Some(999_999), histogram.percentile(100.0));
+    }
+}
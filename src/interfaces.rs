@@ -1,5 +1,10 @@
+use std::collections::BTreeMap;
+
 use async_trait::async_trait;
 use mockall::automock;
+use solana_program::pubkey::Pubkey;
+
+use crate::{account_provider::AccountFetches, error::IntegrityVerificationError};
 
 #[automock]
 #[async_trait]
@@ -10,6 +15,12 @@ pub trait IntegrityVerificationKeysFetcher {
     async fn get_verification_required_groups_keys(&self) -> Result<Vec<String>, String>;
     async fn get_verification_required_assets_keys(&self) -> Result<Vec<String>, String>;
     async fn get_verification_required_assets_proof_keys(&self) -> Result<Vec<String>, String>;
+    async fn get_verification_required_asset_proofs_batches(
+        &self,
+    ) -> Result<Vec<Vec<String>>, String>;
+    async fn get_verification_required_asset_proof_batch_batches(
+        &self,
+    ) -> Result<Vec<Vec<String>>, String>;
     async fn get_verification_required_tokens_by_owner(&self) -> Result<Vec<String>, String>;
     async fn get_verification_required_tokens_by_mint(&self) -> Result<Vec<String>, String>;
     async fn get_verification_required_tokens_by_owner_and_mint(
@@ -17,3 +28,29 @@ pub trait IntegrityVerificationKeysFetcher {
     ) -> Result<Vec<(String, String)>, String>;
     async fn get_verification_required_signatures_for_asset(&self) -> Result<Vec<String>, String>;
 }
+
+#[automock]
+#[async_trait]
+pub trait AccountProvider {
+    /// Returns the data of the account, `None` if it does not exist
+    async fn get_account_data(
+        &self,
+        pubkey: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, IntegrityVerificationError>;
+
+    /// Returns the data of the account and the slot it was read at, not older
+    /// than `min_slot`. Providers without slots read everything at slot 0
+    async fn get_account_at_slot(
+        &self,
+        pubkey: &Pubkey,
+        _min_slot: Option<u64>,
+    ) -> Result<Option<(Vec<u8>, u64)>, IntegrityVerificationError> {
+        Ok(self.get_account_data(pubkey).await?.map(|data| (data, 0)))
+    }
+
+    /// Returns the fetches sent to every RPC node by its URL, empty for
+    /// providers without nodes
+    fn account_fetches(&self) -> BTreeMap<String, AccountFetches> {
+        BTreeMap::new()
+    }
+}
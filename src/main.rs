@@ -1,7 +1,9 @@
+use crate::capacity_search::run_capacity_search;
 use crate::config::setup_config;
 use crate::diff_checker::{
     DiffChecker, GET_ASSET_BY_AUTHORITY_METHOD, GET_ASSET_BY_CREATOR_METHOD,
-    GET_ASSET_BY_GROUP_METHOD, GET_ASSET_BY_OWNER_METHOD, GET_ASSET_METHOD, GET_ASSET_PROOF_METHOD,
+    GET_ASSET_BY_GROUP_METHOD, GET_ASSET_BY_OWNER_METHOD, GET_ASSET_METHOD,
+    GET_ASSET_PROOFS_METHOD, GET_ASSET_PROOF_BATCH_METHOD, GET_ASSET_PROOF_METHOD,
     GET_SIGNATURES_FOR_ASSET, GET_TOKEN_ACCOUNTS_BY_MINT, GET_TOKEN_ACCOUNTS_BY_OWNER,
     GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT,
 };
@@ -10,24 +12,44 @@ use crate::file_keys_fetcher::FileKeysFetcher;
 use crate::graceful_stop::{graceful_stop, listen_shutdown};
 use crate::interfaces::IntegrityVerificationKeysFetcher;
 use clap::Parser;
+use distributed::{run_agent, run_distributed_performance_tests};
+use performance_comparison::run_performance_comparison;
 use performance_measurement::run_performance_tests;
+use replay::run_replay;
 use std::sync::Arc;
 use tokio::task::{JoinError, JoinSet};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
+use tree_sweep::run_tree_sweep;
 
+mod account_provider;
 mod api;
 mod api_req_params;
+mod baseline;
+mod capacity_search;
+mod compressed_leaf;
 mod config;
 mod diff_checker;
+mod distributed;
 mod error;
 mod file_keys_fetcher;
 mod graceful_stop;
+mod histogram;
 mod interfaces;
 mod merkle_tree;
 mod params_generation;
+mod performance_comparison;
 mod performance_measurement;
+mod proof_validator;
+mod replay;
 mod requests;
+mod response_validation;
+mod scenarios;
+mod slowest_keys;
+mod time_series;
+mod timing_breakdown;
+mod tree_cache;
+mod tree_sweep;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -35,12 +57,30 @@ struct Args {
     config_path: String,
     #[arg(short, long)]
     test_type: TestsType,
+    /// File to save the performance results summary to
+    #[arg(long)]
+    results_path: Option<String>,
+    /// Results file of a previous performance run to check for regressions against
+    #[arg(long)]
+    compare_baseline: Option<String>,
+    /// Address an agent listens on for its controller
+    #[arg(long, default_value_t = String::from("127.0.0.1:7878"))]
+    listen: String,
+    /// Token a controller has to present before an agent accepts its config
+    #[arg(long, env = "DAS_AGENT_TOKEN", hide_env_values = true)]
+    agent_token: Option<String>,
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
 enum TestsType {
     Integrity,
     Performance,
+    Capacity,
+    Comparison,
+    Agent,
+    Controller,
+    Replay,
+    TreeSweep,
 }
 
 #[tokio::main(flavor = "multi_thread")]
@@ -49,6 +89,15 @@ async fn main() -> Result<(), IntegrityVerificationError> {
     env_logger::init();
     info!("DAS-API tests start");
 
+    // Agents receive their config from the controller
+    if let TestsType::Agent = args.test_type {
+        let token = args
+            .agent_token
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| IntegrityVerificationError::ValidateConfig("agent_token".to_string()))?;
+        return run_agent(&args.listen, &token).await;
+    }
+
     let config = setup_config(args.config_path.as_str())?;
 
     match args.test_type {
@@ -71,14 +120,48 @@ async fn main() -> Result<(), IntegrityVerificationError> {
             diff_checker.show_results().await;
         }
         TestsType::Performance => {
+            let cancel_token = CancellationToken::new();
+            listen_shutdown(cancel_token.clone()).await;
             run_performance_tests(
-                config.num_of_virtual_users,
-                config.test_duration_time,
-                config.testing_host,
-                config.testing_file_path.clone(),
+                &config,
+                args.results_path.as_deref(),
+                args.compare_baseline.as_deref(),
+                cancel_token,
             )
-            .await;
+            .await?;
+        }
+        TestsType::Capacity => {
+            let cancel_token = CancellationToken::new();
+            listen_shutdown(cancel_token.clone()).await;
+            run_capacity_search(&config, cancel_token).await?;
+        }
+        TestsType::Comparison => {
+            let cancel_token = CancellationToken::new();
+            listen_shutdown(cancel_token.clone()).await;
+            run_performance_comparison(&config, cancel_token).await?;
+        }
+        TestsType::Controller => {
+            let cancel_token = CancellationToken::new();
+            listen_shutdown(cancel_token.clone()).await;
+            run_distributed_performance_tests(
+                &config,
+                args.results_path.as_deref(),
+                args.compare_baseline.as_deref(),
+                cancel_token,
+            )
+            .await?;
+        }
+        TestsType::Replay => {
+            let cancel_token = CancellationToken::new();
+            listen_shutdown(cancel_token.clone()).await;
+            run_replay(&config, cancel_token).await?;
+        }
+        TestsType::TreeSweep => {
+            let cancel_token = CancellationToken::new();
+            listen_shutdown(cancel_token.clone()).await;
+            run_tree_sweep(&config, cancel_token).await?;
         }
+        TestsType::Agent => unreachable!("agents run without a config"),
     }
 
     Ok(())
@@ -123,6 +206,20 @@ async fn run_tests<T>(
         GET_ASSET_PROOF_METHOD,
         cancel_token
     );
+    spawn_test!(
+        tasks,
+        diff_checker,
+        check_get_asset_proofs,
+        GET_ASSET_PROOFS_METHOD,
+        cancel_token
+    );
+    spawn_test!(
+        tasks,
+        diff_checker,
+        check_get_asset_proof_batch,
+        GET_ASSET_PROOF_BATCH_METHOD,
+        cancel_token
+    );
     spawn_test!(
         tasks,
         diff_checker,
@@ -1,53 +1,344 @@
-#[macro_export]
-macro_rules! _check_proof {
-    ($max_depth:literal, $max_size:literal, &$bytes:ident, $initial_proofs:ident, $leaf:ident, $leaf_index:ident) => {{
-        let mut proof: [spl_concurrent_merkle_tree::node::Node; $max_depth] =
-            [spl_concurrent_merkle_tree::node::Node::default(); $max_depth];
-        spl_concurrent_merkle_tree::hash::fill_in_proof::<$max_depth>(
-            $initial_proofs.as_ref(),
-            &mut proof,
-        );
-        let tree = spl_concurrent_merkle_tree::concurrent_merkle_tree::ConcurrentMerkleTree::<
-            $max_depth,
-            $max_size,
-        >::load_bytes($bytes)?;
-
-        Ok(tree.check_valid_proof($leaf, &proof, $leaf_index))
-    }};
-}
-
-#[macro_export]
-macro_rules! check_proof {
-    (&$header:ident, $($arg:tt)*) => {
-        // Note: max_buffer_size MUST be a power of 2
-        match ($header.get_max_depth(), $header.get_max_buffer_size()) {
-            (3, 8) => _check_proof!(3, 8, $($arg)*),
-            (5, 8) => _check_proof!(5, 8, $($arg)*),
-            (14, 64) => _check_proof!(14, 64, $($arg)*),
-            (14, 256) => _check_proof!(14, 256, $($arg)*),
-            (14, 1024) => _check_proof!(14, 1024, $($arg)*),
-            (14, 2048) => _check_proof!(14, 2048, $($arg)*),
-            (15, 64) => _check_proof!(15, 64, $($arg)*),
-            (16, 64) => _check_proof!(16, 64, $($arg)*),
-            (17, 64) => _check_proof!(17, 64, $($arg)*),
-            (18, 64) => _check_proof!(18, 64, $($arg)*),
-            (19, 64) => _check_proof!(19, 64, $($arg)*),
-            (20, 64) => _check_proof!(20, 64, $($arg)*),
-            (20, 256) => _check_proof!(20, 256, $($arg)*),
-            (20, 1024) => _check_proof!(20, 1024, $($arg)*),
-            (20, 2048) => _check_proof!(20, 2048, $($arg)*),
-            (24, 64) => _check_proof!(24, 64, $($arg)*),
-            (24, 256) => _check_proof!(24, 256, $($arg)*),
-            (24, 512) => _check_proof!(24, 512, $($arg)*),
-            (24, 1024) => _check_proof!(24, 1024, $($arg)*),
-            (24, 2048) => _check_proof!(24, 2048, $($arg)*),
-            (26, 512) => _check_proof!(26, 512, $($arg)*),
-            (26, 1024) => _check_proof!(26, 1024, $($arg)*),
-            (26, 2048) => _check_proof!(26, 2048, $($arg)*),
-            (30, 512) => _check_proof!(30, 512, $($arg)*),
-            (30, 1024) => _check_proof!(30, 1024, $($arg)*),
-            (30, 2048) => _check_proof!(30, 2048, $($arg)*),
-            _ => Err(IntegrityVerificationError::CannotCreateMerkleTree($header.get_max_depth(), $header.get_max_buffer_size())),
+use crate::error::IntegrityVerificationError;
+use anchor_lang::AnchorDeserialize;
+use spl_account_compression::canopy::fill_in_proof_from_canopy;
+use spl_account_compression::state::{
+    ConcurrentMerkleTreeHeader, CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1,
+};
+use spl_concurrent_merkle_tree::hash::recompute;
+use spl_concurrent_merkle_tree::node::{empty_node, Node, EMPTY};
+
+const NODE_SIZE: usize = 32;
+// Sequence number, active index and buffer size, all u64
+const TREE_PREFIX_SIZE: usize = 24;
+// Index and padding after the nodes of a changelog or of the rightmost path
+const PATH_SUFFIX_SIZE: usize = 8;
+// Deepest tree account compression supports
+const MAX_SUPPORTED_DEPTH: u32 = 30;
+
+/// Position of a root among the roots of the tree account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RootMatch {
+    Current,
+    // Root before one of the changes still kept in the changelog buffer,
+    // `age` changes ago
+    Recent { age: u64 },
+    None,
+}
+
+/// How the number of nodes of a proof fits the tree it is checked against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProofLength {
+    // A node for every level of the tree
+    Full,
+    // Nodes up to the canopy, which holds the rest
+    CanopyTrimmed,
+    // Nodes up to a level inside the canopy, which holds the rest
+    PartiallyTrimmed,
+    // Fewer nodes than the canopy needs to complete the proof
+    Truncated,
+    // More nodes than the tree has levels
+    Overlong,
+}
+
+/// Size of the `ConcurrentMerkleTree` of the given shape, changelogs and the
+/// rightmost path are a root or leaf, `max_depth` nodes and a suffix each
+fn tree_size(max_depth: u32, max_buffer_size: u32) -> usize {
+    let path_size = (max_depth as usize + 1) * NODE_SIZE + PATH_SUFFIX_SIZE;
+    TREE_PREFIX_SIZE + max_buffer_size as usize * path_size + path_size
+}
+
+/// Concurrent merkle tree account read without knowing its depth and buffer
+/// size at compile time
+pub struct TreeAccount<'a> {
+    max_depth: u32,
+    max_buffer_size: u32,
+    canopy_depth: u32,
+    tree: &'a [u8],
+    canopy: &'a [u8],
+}
+
+impl<'a> TreeAccount<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, IntegrityVerificationError> {
+        if data.len() < CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1 {
+            return Err(IntegrityVerificationError::CannotCreateMerkleTree(0, 0));
+        }
+        let (header_bytes, rest) = data.split_at(CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1);
+        let header = ConcurrentMerkleTreeHeader::try_from_slice(header_bytes)?;
+        let (max_depth, max_buffer_size) = (header.get_max_depth(), header.get_max_buffer_size());
+
+        let tree_size = tree_size(max_depth, max_buffer_size);
+        if max_depth == 0
+            || max_depth > MAX_SUPPORTED_DEPTH
+            || max_buffer_size == 0
+            || rest.len() < tree_size
+        {
+            return Err(IntegrityVerificationError::CannotCreateMerkleTree(
+                max_depth,
+                max_buffer_size,
+            ));
         }
+        let (tree, canopy) = rest.split_at(tree_size);
+        // The canopy holds every node of its top levels but the root
+        let canopy_nodes = canopy.len() / NODE_SIZE + 2;
+        if canopy.len() % NODE_SIZE != 0
+            || !canopy_nodes.is_power_of_two()
+            || canopy_nodes > 1 << (max_depth + 1)
+        {
+            return Err(IntegrityVerificationError::CannotCreateMerkleTree(
+                max_depth,
+                max_buffer_size,
+            ));
+        }
+
+        Ok(Self {
+            max_depth,
+            max_buffer_size,
+            canopy_depth: canopy_nodes.trailing_zeros() - 1,
+            tree,
+            canopy,
+        })
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(
+            self.tree[offset..offset + 8]
+                .try_into()
+                .expect("slice of 8 bytes"),
+        )
+    }
+
+    fn changelog_root(&self, index: u64) -> Node {
+        let changelog_size = (self.max_depth as usize + 1) * NODE_SIZE + PATH_SUFFIX_SIZE;
+        let offset = TREE_PREFIX_SIZE + index as usize * changelog_size;
+        self.tree[offset..offset + NODE_SIZE]
+            .try_into()
+            .expect("slice of a node")
+    }
+
+    /// Returns the roots kept in the changelog buffer, from the current one to
+    /// the oldest, none if the tree is not initialized
+    pub fn roots(&self) -> Vec<Node> {
+        let sequence_number = self.read_u64(0);
+        let active_index = self.read_u64(8);
+        let buffer_size = self.read_u64(16);
+        if sequence_number == 0 && active_index == 0 && buffer_size == 0 {
+            return Vec::new();
+        }
+
+        let max_buffer_size = self.max_buffer_size as u64;
+        (0..buffer_size.min(max_buffer_size))
+            .map(|age| {
+                self.changelog_root((active_index + max_buffer_size - age) % max_buffer_size)
+            })
+            .collect()
+    }
+
+    /// Hashes `leaf` up to the root with `proof`, completed from the canopy and
+    /// with empty nodes. Returns `None` if the leaf index or the proof does not
+    /// fit the depth of the tree
+    pub fn compute_root(
+        &self,
+        leaf: Node,
+        proof: &[Node],
+        leaf_index: u32,
+    ) -> Result<Option<Node>, IntegrityVerificationError> {
+        let max_depth = self.max_depth as usize;
+        if leaf_index as u64 >= 1 << max_depth {
+            return Ok(None);
+        }
+        let mut proof = proof.to_vec();
+        fill_in_proof_from_canopy(self.canopy, self.max_depth, leaf_index, &mut proof)?;
+        if proof.len() > max_depth {
+            return Ok(None);
+        }
+        for level in proof.len()..max_depth {
+            proof.push(empty_node(level as u32));
+        }
+
+        Ok(Some(recompute(leaf, &proof, leaf_index)))
+    }
+
+    pub fn proof_length(&self, nodes: usize) -> ProofLength {
+        let max_depth = self.max_depth as usize;
+        let below_canopy = max_depth - self.canopy_depth as usize;
+        match nodes {
+            nodes if nodes == max_depth => ProofLength::Full,
+            nodes if nodes > max_depth => ProofLength::Overlong,
+            nodes if nodes == below_canopy => ProofLength::CanopyTrimmed,
+            nodes if nodes > below_canopy => ProofLength::PartiallyTrimmed,
+            _ => ProofLength::Truncated,
+        }
+    }
+
+    /// Returns whether the nodes of `proof` the canopy holds as well are the
+    /// same there, which they are if both are of the current root
+    pub fn canopy_agrees(
+        &self,
+        proof: &[Node],
+        leaf_index: u32,
+    ) -> Result<bool, IntegrityVerificationError> {
+        let max_depth = self.max_depth as usize;
+        let below_canopy = max_depth - self.canopy_depth as usize;
+        // Nothing to compare, `compute_root` rejects overlong proofs anyway
+        if proof.len() <= below_canopy
+            || proof.len() > max_depth
+            || leaf_index as u64 >= 1 << max_depth
+        {
+            return Ok(true);
+        }
+        let mut from_canopy = vec![EMPTY; below_canopy];
+        fill_in_proof_from_canopy(self.canopy, self.max_depth, leaf_index, &mut from_canopy)?;
+        Ok(from_canopy[below_canopy..proof.len()] == proof[below_canopy..])
+    }
+
+    pub fn find_root(&self, root: &Node) -> RootMatch {
+        match self.roots().iter().position(|r| r == root) {
+            Some(0) => RootMatch::Current,
+            Some(age) => RootMatch::Recent { age: age as u64 },
+            None => RootMatch::None,
+        }
+    }
+}
+
+/// Returns the data of a tree account of the given shape holding `leaves`
+#[cfg(test)]
+pub fn test_tree_account<const MAX_DEPTH: usize, const MAX_BUFFER_SIZE: usize>(
+    leaves: &[Node],
+) -> Vec<u8> {
+    test_tree_account_with_canopy::<MAX_DEPTH, MAX_BUFFER_SIZE>(leaves, 0)
+}
+
+/// Returns the data of a tree account of the given shape holding `leaves`,
+/// with a canopy of `canopy_depth` levels
+#[cfg(test)]
+pub fn test_tree_account_with_canopy<const MAX_DEPTH: usize, const MAX_BUFFER_SIZE: usize>(
+    leaves: &[Node],
+    canopy_depth: u32,
+) -> Vec<u8> {
+    use anchor_lang::AnchorSerialize;
+    use solana_program::pubkey::Pubkey;
+    use spl_account_compression::zero_copy::ZeroCopy;
+    use spl_concurrent_merkle_tree::concurrent_merkle_tree::ConcurrentMerkleTree;
+
+    let mut data = vec![0; CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1];
+    let mut header = ConcurrentMerkleTreeHeader::try_from_slice(&data).unwrap();
+    header.initialize(
+        MAX_DEPTH as u32,
+        MAX_BUFFER_SIZE as u32,
+        &Pubkey::default(),
+        0,
+    );
+    header.serialize(&mut data.as_mut_slice()).unwrap();
+
+    data.resize(
+        data.len() + std::mem::size_of::<ConcurrentMerkleTree<MAX_DEPTH, MAX_BUFFER_SIZE>>(),
+        0,
+    );
+    let tree = ConcurrentMerkleTree::<MAX_DEPTH, MAX_BUFFER_SIZE>::load_mut_bytes(
+        &mut data[CONCURRENT_MERKLE_TREE_HEADER_SIZE_V1..],
+    )
+    .unwrap();
+    tree.initialize().unwrap();
+    for leaf in leaves {
+        tree.append(*leaf).unwrap();
+    }
+
+    // Nodes of every level from the leaves up, the canopy takes the top ones
+    let mut level = leaves.to_vec();
+    level.resize(1 << MAX_DEPTH, EMPTY);
+    let mut levels = vec![level];
+    while levels.last().unwrap().len() > 1 {
+        let parents = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| solana_program::keccak::hashv(&[&pair[0], &pair[1]]).to_bytes())
+            .collect();
+        levels.push(parents);
+    }
+    for depth in 1..=canopy_depth as usize {
+        for node in levels[MAX_DEPTH - depth].iter() {
+            data.extend_from_slice(node);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        test_tree_account, test_tree_account_with_canopy, tree_size, ProofLength, RootMatch,
+        TreeAccount,
     };
+    use spl_concurrent_merkle_tree::concurrent_merkle_tree::ConcurrentMerkleTree;
+    use spl_concurrent_merkle_tree::node::empty_node;
+    use std::mem::size_of;
+
+    #[test]
+    fn test_verify_any_shape() {
+        assert_eq!(size_of::<ConcurrentMerkleTree<3, 8>>(), tree_size(3, 8));
+        assert_eq!(size_of::<ConcurrentMerkleTree<14, 64>>(), tree_size(14, 64));
+
+        // Not a shape account compression lists
+        let (first, second, third) = ([1; 32], [2; 32], [3; 32]);
+        let data = test_tree_account::<4, 2>(&[first, second, third]);
+        let tree = TreeAccount::parse(&data).unwrap();
+        assert_eq!(2, tree.roots().len());
+
+        let verify = |leaf, proof: &[[u8; 32]], leaf_index| {
+            tree.compute_root(leaf, proof, leaf_index)
+                .unwrap()
+                .map_or(RootMatch::None, |root| tree.find_root(&root))
+        };
+        let mut pair = third;
+        spl_concurrent_merkle_tree::hash::hash_to_parent(&mut pair, &empty_node(0), true);
+        assert_eq!(
+            RootMatch::Current,
+            verify(first, &[second, pair, empty_node(2), empty_node(3)], 0)
+        );
+        // Proofs of the tree before the last append
+        assert_eq!(RootMatch::Recent { age: 1 }, verify(first, &[second], 0));
+        // Outside of the changelog buffer
+        assert_eq!(RootMatch::None, verify(first, &[], 0));
+        assert_eq!(RootMatch::None, verify(first, &[third], 0));
+        assert_eq!(RootMatch::None, verify(first, &[], 16));
+    }
+
+    #[test]
+    fn test_proof_length_and_canopy() {
+        let leaves = [[1; 32], [2; 32], [3; 32]];
+        let data = test_tree_account_with_canopy::<3, 8>(&leaves, 1);
+        let tree = TreeAccount::parse(&data).unwrap();
+        assert_eq!(ProofLength::Full, tree.proof_length(3));
+        assert_eq!(ProofLength::CanopyTrimmed, tree.proof_length(2));
+        assert_eq!(ProofLength::Truncated, tree.proof_length(1));
+        assert_eq!(ProofLength::Overlong, tree.proof_length(4));
+
+        let mut pair = leaves[2];
+        spl_concurrent_merkle_tree::hash::hash_to_parent(&mut pair, &empty_node(0), true);
+        let full = [leaves[1], pair, empty_node(2)];
+        assert!(tree.canopy_agrees(&full, 0).unwrap());
+        let current_root = tree.roots()[0];
+        assert_eq!(
+            Some(current_root),
+            tree.compute_root(leaves[0], &full[..2], 0).unwrap()
+        );
+        assert!(!tree.canopy_agrees(&[leaves[1], pair, [9; 32]], 0).unwrap());
+
+        // Canopy of a size no tree has
+        assert!(TreeAccount::parse(&data[..data.len() - 32]).is_err());
+
+        // Proofs may stop at any level of a deeper canopy
+        let data = test_tree_account_with_canopy::<3, 8>(&leaves, 2);
+        let tree = TreeAccount::parse(&data).unwrap();
+        assert_eq!(ProofLength::CanopyTrimmed, tree.proof_length(1));
+        assert_eq!(ProofLength::PartiallyTrimmed, tree.proof_length(2));
+        assert_eq!(ProofLength::Truncated, tree.proof_length(0));
+        assert_eq!(
+            Some(current_root),
+            tree.compute_root(leaves[0], &full[..2], 0).unwrap()
+        );
+        assert!(tree.canopy_agrees(&full[..2], 0).unwrap());
+        assert!(!tree.canopy_agrees(&[leaves[1], [9; 32]], 0).unwrap());
+    }
 }
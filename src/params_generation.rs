@@ -1,35 +1,80 @@
 use crate::api_req_params::{
-    AssetSortBy, AssetSortDirection, AssetSorting, GetAsset, GetAssetProof, GetAssetSignatures,
-    GetAssetsByAuthority, GetAssetsByCreator, GetAssetsByGroup, GetAssetsByOwner, GetTokenAccounts,
+    AssetSortBy, AssetSortDirection, AssetSorting, GetAsset, GetAssetProof, GetAssetProofs,
+    GetAssetSignatures, GetAssetsByAuthority, GetAssetsByCreator, GetAssetsByGroup,
+    GetAssetsByOwner, GetTokenAccounts,
 };
+use rand::seq::SliceRandom;
 use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
 
 const GROUP_KEY: &str = "collection";
 const MIN_LIMIT: u32 = 1;
 const MAX_LIMIT: u32 = 1000;
 
-fn get_random_asset_sorting_arg() -> Option<AssetSorting> {
-    let mut rng = rand::thread_rng();
-    if rng.gen() {
+/// Narrows down the random request parameters generated for a method.
+/// Unset fields keep the default fully random behaviour
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ParamsDistribution {
+    pub min_limit: Option<u32>,
+    pub max_limit: Option<u32>,
+    // `null` entries stand for requests without the parameter
+    pub sort_by: Option<Vec<Option<AssetSortBy>>>,
+    pub sort_direction: Option<Vec<Option<AssetSortDirection>>>,
+}
+
+impl ParamsDistribution {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_limit.unwrap_or(MIN_LIMIT) > self.max_limit.unwrap_or(MAX_LIMIT) {
+            return Err("min_limit is greater than max_limit".to_string());
+        }
+        if self.sort_by.as_ref().is_some_and(Vec::is_empty)
+            || self.sort_direction.as_ref().is_some_and(Vec::is_empty)
+        {
+            return Err("empty sort choices".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn get_random_asset_sorting_arg(
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
+) -> Option<AssetSorting> {
+    if let Some(choices) = &distribution.sort_by {
+        return choices
+            .choose(rnd)
+            .cloned()
+            .flatten()
+            .map(|sort_by| AssetSorting {
+                sort_by,
+                sort_direction: get_random_sort_direction(distribution, rnd),
+            });
+    }
+    if rnd.gen() {
         return None;
     }
 
     Some(AssetSorting {
-        sort_by: match rng.gen_range(0..4) {
+        sort_by: match rnd.gen_range(0..4) {
             0 => AssetSortBy::Created,
             1 => AssetSortBy::Updated,
             2 => AssetSortBy::RecentAction,
             _ => AssetSortBy::None,
         },
-        sort_direction: get_random_sort_direction(),
+        sort_direction: get_random_sort_direction(distribution, rnd),
     })
 }
 
-fn get_random_sort_direction() -> Option<AssetSortDirection> {
-    let mut rng = rand::thread_rng();
+fn get_random_sort_direction(
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
+) -> Option<AssetSortDirection> {
+    if let Some(choices) = &distribution.sort_direction {
+        return choices.choose(rnd).cloned().flatten();
+    }
 
-    if rng.gen() {
-        Some(if rng.gen() {
+    if rnd.gen() {
+        Some(if rnd.gen() {
             AssetSortDirection::Asc
         } else {
             AssetSortDirection::Desc
@@ -39,35 +84,41 @@ fn get_random_sort_direction() -> Option<AssetSortDirection> {
     }
 }
 
-fn get_random_limit_arg() -> Option<u32> {
-    let mut rng = rand::thread_rng();
-    if rng.gen() {
-        Some(rng.gen_range(MIN_LIMIT..=MAX_LIMIT))
+fn get_random_limit_arg(distribution: &ParamsDistribution, rnd: &mut impl Rng) -> Option<u32> {
+    if distribution.min_limit.is_some() || distribution.max_limit.is_some() {
+        return Some(rnd.gen_range(
+            distribution.min_limit.unwrap_or(MIN_LIMIT)
+                ..=distribution.max_limit.unwrap_or(MAX_LIMIT),
+        ));
+    }
+    if rnd.gen() {
+        Some(rnd.gen_range(MIN_LIMIT..=MAX_LIMIT))
     } else {
         None
     }
 }
 
-fn get_random_page_arg() -> u32 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(1..=5)
+fn get_random_page_arg(rnd: &mut impl Rng) -> u32 {
+    rnd.gen_range(1..=5)
 }
 
 pub fn generate_get_assets_by_group_params(
     group_value: String,
     before: Option<String>,
     after: Option<String>,
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
 ) -> GetAssetsByGroup {
     let page = if after.is_none() && before.is_none() {
-        Some(get_random_page_arg())
+        Some(get_random_page_arg(rnd))
     } else {
         None
     };
     GetAssetsByGroup {
         group_key: GROUP_KEY.to_string(),
         group_value,
-        sort_by: get_random_asset_sorting_arg(),
-        limit: get_random_limit_arg(),
+        sort_by: get_random_asset_sorting_arg(distribution, rnd),
+        limit: get_random_limit_arg(distribution, rnd),
         page,
         before,
         after,
@@ -78,16 +129,18 @@ pub fn generate_get_assets_by_owner_params(
     owner_address: String,
     before: Option<String>,
     after: Option<String>,
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
 ) -> GetAssetsByOwner {
     let page = if after.is_none() && before.is_none() {
-        Some(get_random_page_arg())
+        Some(get_random_page_arg(rnd))
     } else {
         None
     };
     GetAssetsByOwner {
         owner_address,
-        sort_by: get_random_asset_sorting_arg(),
-        limit: get_random_limit_arg(),
+        sort_by: get_random_asset_sorting_arg(distribution, rnd),
+        limit: get_random_limit_arg(distribution, rnd),
         page,
         before,
         after,
@@ -98,18 +151,19 @@ pub fn generate_get_assets_by_creator_params(
     creator_address: String,
     before: Option<String>,
     after: Option<String>,
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
 ) -> GetAssetsByCreator {
-    let mut rng = rand::thread_rng();
     let page = if after.is_none() && before.is_none() {
-        Some(get_random_page_arg())
+        Some(get_random_page_arg(rnd))
     } else {
         None
     };
     GetAssetsByCreator {
         creator_address,
-        only_verified: if rng.gen() { Some(rng.gen()) } else { None },
-        sort_by: get_random_asset_sorting_arg(),
-        limit: get_random_limit_arg(),
+        only_verified: if rnd.gen() { Some(rnd.gen()) } else { None },
+        sort_by: get_random_asset_sorting_arg(distribution, rnd),
+        limit: get_random_limit_arg(distribution, rnd),
         page,
         before,
         after,
@@ -120,16 +174,18 @@ pub fn generate_get_assets_by_authority_params(
     authority_address: String,
     before: Option<String>,
     after: Option<String>,
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
 ) -> GetAssetsByAuthority {
     let page = if after.is_none() && before.is_none() {
-        Some(get_random_page_arg())
+        Some(get_random_page_arg(rnd))
     } else {
         None
     };
     GetAssetsByAuthority {
         authority_address,
-        sort_by: get_random_asset_sorting_arg(),
-        limit: get_random_limit_arg(),
+        sort_by: get_random_asset_sorting_arg(distribution, rnd),
+        limit: get_random_limit_arg(distribution, rnd),
         page,
         before,
         after,
@@ -144,29 +200,39 @@ pub fn generate_get_asset_proof_params(id: String) -> GetAssetProof {
     GetAssetProof { id }
 }
 
+pub fn generate_get_asset_proofs_params(ids: Vec<String>) -> GetAssetProofs {
+    GetAssetProofs { ids }
+}
+
 pub fn generate_get_token_accounts(
     owner: Option<String>,
     mint: Option<String>,
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
 ) -> GetTokenAccounts {
     GetTokenAccounts {
-        limit: get_random_limit_arg(),
-        page: Some(get_random_page_arg()),
+        limit: get_random_limit_arg(distribution, rnd),
+        page: Some(get_random_page_arg(rnd)),
         owner,
         mint,
         options: None,
     }
 }
 
-pub fn generate_get_signatures_for_asset(asset: String) -> GetAssetSignatures {
+pub fn generate_get_signatures_for_asset(
+    asset: String,
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
+) -> GetAssetSignatures {
     GetAssetSignatures {
         id: Some(asset),
-        limit: get_random_limit_arg(),
-        page: Some(get_random_page_arg()),
+        limit: get_random_limit_arg(distribution, rnd),
+        page: Some(get_random_page_arg(rnd)),
         before: None,
         after: None,
         tree: None,
         leaf_index: None,
-        sort_direction: get_random_sort_direction(),
+        sort_direction: get_random_sort_direction(distribution, rnd),
         cursor: None,
     }
 }
@@ -0,0 +1,216 @@
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    config::IntegrityVerificationConfig,
+    error::IntegrityVerificationError,
+    performance_measurement::{run_load_stage, LoadProfile, RequestStats, Stats, Summary},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Faster,
+    Slower,
+    Equivalent,
+    MoreErrors,
+    NoData,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = match self {
+            Verdict::Faster => "testing host is FASTER",
+            Verdict::Slower => "testing host is SLOWER",
+            Verdict::Equivalent => "hosts are EQUIVALENT",
+            Verdict::MoreErrors => "testing host has MORE ERRORS",
+            Verdict::NoData => "NOT ENOUGH DATA",
+        };
+        write!(f, "{}", verdict)
+    }
+}
+
+pub fn delta_percent(reference: f64, testing: f64) -> f64 {
+    if reference == 0.0 {
+        return 0.0;
+    }
+    (testing - reference) / reference * 100.0
+}
+
+/// Judges the testing host by its p95 response time, unless it fails noticeably
+/// more often than the reference one
+pub fn verdict(reference: &Summary, testing: &Summary, tolerance_percent: f64) -> Verdict {
+    if reference.requests == 0 || testing.requests == 0 {
+        return Verdict::NoData;
+    }
+    if (testing.error_rate - reference.error_rate) * 100.0 > tolerance_percent {
+        return Verdict::MoreErrors;
+    }
+
+    let p95_delta = delta_percent(reference.p95 as f64, testing.p95 as f64);
+    if p95_delta < -tolerance_percent {
+        Verdict::Faster
+    } else if p95_delta > tolerance_percent {
+        Verdict::Slower
+    } else {
+        Verdict::Equivalent
+    }
+}
+
+pub struct ComparisonReport {
+    test_duration: u64, // seconds
+    tolerance_percent: f64,
+    reference: Stats,
+    testing: Stats,
+}
+
+impl ComparisonReport {
+    fn write_comparison(
+        &self,
+        f: &mut fmt::Formatter,
+        reference: &Summary,
+        testing: &Summary,
+    ) -> fmt::Result {
+        write!(
+            f,
+            "\nReference: {}\nTesting: {}\nDelta: RPS {:+.2}%, p50 {:+.2}%, p95 {:+.2}%, p99 {:+.2}%, error rate {:+.4}\nVerdict: {}\n",
+            reference,
+            testing,
+            delta_percent(reference.rps, testing.rps),
+            delta_percent(reference.p50 as f64, testing.p50 as f64),
+            delta_percent(reference.p95 as f64, testing.p95 as f64),
+            delta_percent(reference.p99 as f64, testing.p99 as f64),
+            testing.error_rate - reference.error_rate,
+            verdict(reference, testing, self.tolerance_percent),
+        )
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.reference.interrupted_after().is_some()
+            || self.testing.interrupted_after().is_some()
+        {
+            write!(f, "\nComparison was interrupted, results are partial\n")?;
+        }
+        // An interrupted host is rated by the time it was actually loaded for
+        let reference_duration = self.reference.duration(self.test_duration);
+        let testing_duration = self.testing.duration(self.test_duration);
+
+        write!(f, "\n---\nOverall:")?;
+        self.write_comparison(
+            f,
+            &Summary::new(self.reference.overall(), reference_duration),
+            &Summary::new(self.testing.overall(), testing_duration),
+        )?;
+
+        let methods: BTreeSet<&String> = self
+            .reference
+            .methods()
+            .keys()
+            .chain(self.testing.methods().keys())
+            .collect();
+        let empty = RequestStats::default();
+        for method in methods {
+            write!(f, "---\n{}:", method)?;
+            self.write_comparison(
+                f,
+                &Summary::new(
+                    self.reference.methods().get(method).unwrap_or(&empty),
+                    reference_duration,
+                ),
+                &Summary::new(
+                    self.testing.methods().get(method).unwrap_or(&empty),
+                    testing_duration,
+                ),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn run_performance_comparison(
+    config: &IntegrityVerificationConfig,
+    cancel_token: CancellationToken,
+) -> Result<(), IntegrityVerificationError> {
+    // Both hosts receive the very same requests in the same order
+    let profile = LoadProfile::from_config(config)
+        .await?
+        .with_fixed_sequence(config.comparison_sequence_length);
+    info!("Key selection: {}", profile.key_selection());
+
+    let reference_fut = run_load_stage(
+        config.num_of_virtual_users,
+        config.test_duration_time,
+        &config.reference_host,
+        &profile,
+        &cancel_token,
+    );
+    let testing_fut = run_load_stage(
+        config.num_of_virtual_users,
+        config.test_duration_time,
+        &config.testing_host,
+        &profile,
+        &cancel_token,
+    );
+
+    let (reference, testing) = if config.comparison_concurrent {
+        info!("Running reference and testing hosts concurrently");
+        tokio::join!(reference_fut, testing_fut)
+    } else {
+        info!("Running reference host");
+        let reference = reference_fut.await;
+        info!("Running testing host");
+        (reference, testing_fut.await)
+    };
+
+    println!(
+        "{}",
+        ComparisonReport {
+            test_duration: config.test_duration_time,
+            tolerance_percent: config.comparison_tolerance_percent,
+            reference,
+            testing,
+        }
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verdict, Summary, Verdict};
+
+    fn summary(p95: u64, error_rate: f64) -> Summary {
+        Summary {
+            requests: 100,
+            rps: 10.0,
+            p50: p95 / 2,
+            p95,
+            p99: p95 * 2,
+            error_rate,
+            bytes_per_sec: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_verdict() {
+        assert_eq!(
+            Verdict::Faster,
+            verdict(&summary(100, 0.0), &summary(80, 0.0), 10.0)
+        );
+        assert_eq!(
+            Verdict::Slower,
+            verdict(&summary(100, 0.0), &summary(120, 0.0), 10.0)
+        );
+        assert_eq!(
+            Verdict::Equivalent,
+            verdict(&summary(100, 0.0), &summary(105, 0.0), 10.0)
+        );
+        assert_eq!(
+            Verdict::MoreErrors,
+            verdict(&summary(100, 0.0), &summary(50, 0.2), 10.0)
+        );
+    }
+}
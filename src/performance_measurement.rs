@@ -1,104 +1,357 @@
-use std::{collections::HashMap, fmt, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
 
 use crate::{
-    api::IntegrityVerificationApi,
+    account_provider::{AccountFetches, AccountFetchesReport},
+    api::{IntegrityVerificationApi, RequestTimings},
+    baseline::{compare_with_baseline, PerformanceResults},
+    config::IntegrityVerificationConfig,
     diff_checker::{
         GET_ASSET_BY_AUTHORITY_METHOD, GET_ASSET_BY_CREATOR_METHOD, GET_ASSET_BY_GROUP_METHOD,
-        GET_ASSET_BY_OWNER_METHOD, GET_ASSET_METHOD, GET_ASSET_PROOF_METHOD,
-        GET_SIGNATURES_FOR_ASSET, GET_TOKEN_ACCOUNTS, GET_TOKEN_ACCOUNTS_BY_MINT,
-        GET_TOKEN_ACCOUNTS_BY_OWNER, GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT,
+        GET_ASSET_BY_OWNER_METHOD, GET_ASSET_METHOD, GET_ASSET_PROOFS_METHOD,
+        GET_ASSET_PROOF_BATCH_METHOD, GET_ASSET_PROOF_METHOD, GET_SIGNATURES_FOR_ASSET,
+        GET_TOKEN_ACCOUNTS, GET_TOKEN_ACCOUNTS_BY_MINT, GET_TOKEN_ACCOUNTS_BY_OWNER,
+        GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT,
     },
     error::IntegrityVerificationError,
-    file_keys_fetcher::FileKeysFetcher,
-    graceful_stop,
+    file_keys_fetcher::{parse_key_batch, FileKeysFetcher, KeySelection},
+    histogram::Histogram,
     params_generation::{
         generate_get_asset_params, generate_get_asset_proof_params,
-        generate_get_assets_by_authority_params, generate_get_assets_by_creator_params,
-        generate_get_assets_by_group_params, generate_get_assets_by_owner_params,
-        generate_get_signatures_for_asset, generate_get_token_accounts,
+        generate_get_asset_proofs_params, generate_get_assets_by_authority_params,
+        generate_get_assets_by_creator_params, generate_get_assets_by_group_params,
+        generate_get_assets_by_owner_params, generate_get_signatures_for_asset,
+        generate_get_token_accounts, ParamsDistribution,
     },
+    proof_validator::ProofStatus,
     requests::Body,
+    response_validation::{
+        deserialize_failure_counts, serialize_failure_counts, ProofCheck, ResponseValidator,
+        ValidationFailure,
+    },
+    scenarios::{Journeys, Scenario},
+    slowest_keys::{KeyLatencies, SlowestKeysReport},
+    time_series::{LoadGauges, TimeSeries},
+    timing_breakdown::{PhaseTimings, TimingBreakdown},
 };
-use serde_json::json;
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tokio::{
-    sync::{
-        watch::{self, Receiver},
-        Mutex,
-    },
-    task::JoinSet,
+    sync::watch::{self, Receiver},
+    task::{JoinHandle, JoinSet},
+    time::{Duration, Instant},
 };
-use tracing::{debug, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Commands {
     Init,
     Start(Vec<u32>),
     Stop(Vec<u32>),
+    // Stop without waiting for in-flight requests
+    Abort(Vec<u32>),
 }
 
-pub struct Stats {
-    successful_requests: u64,
+#[derive(Serialize, Deserialize, Default)]
+pub struct RequestStats {
     failed_requests: u64,
-    response_time_millis: Vec<u64>,
-    error_codes: HashMap<u16, u64>,
+    response_times: Histogram,
+    phases: PhaseTimings,
 }
 
-impl Stats {
-    pub fn new() -> Self {
-        Self {
-            successful_requests: 0,
-            failed_requests: 0,
-            response_time_millis: Vec::new(),
-            error_codes: HashMap::new(),
+impl RequestStats {
+    /// Successful requests are the ones whose response time was recorded
+    pub fn successful_requests(&self) -> u64 {
+        self.response_times.count()
+    }
+
+    pub fn total_requests(&self) -> u64 {
+        self.successful_requests() + self.failed_requests
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        let total = self.total_requests();
+        if total == 0 {
+            return 0.0;
         }
+        self.failed_requests as f64 / total as f64
+    }
+
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        self.response_times.percentile(p)
+    }
+
+    pub fn phases(&self) -> &PhaseTimings {
+        &self.phases
     }
 
-    pub fn inc_successful_requests(&mut self) {
-        self.successful_requests += 1;
+    fn record_success(&mut self, time: u64) {
+        self.response_times.record(time);
     }
 
-    pub fn inc_failed_requests(&mut self) {
+    fn record_failure(&mut self) {
         self.failed_requests += 1;
     }
 
-    pub fn add_response_time(&mut self, time: u64) {
-        self.response_time_millis.push(time);
+    fn merge(&mut self, other: &RequestStats) {
+        self.failed_requests += other.failed_requests;
+        self.response_times.merge(&other.response_times);
+        self.phases.merge(&other.phases);
     }
+}
 
-    pub fn inc_error_code(&mut self, code: u16) {
-        if let Some(count) = self.error_codes.get_mut(&code) {
-            *count += 1;
-        } else {
-            self.error_codes.insert(code, 1);
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub requests: u64,
+    pub rps: f64,
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub error_rate: f64,
+    #[serde(default)]
+    pub bytes_per_sec: f64,
+}
+
+impl Summary {
+    pub fn new(stats: &RequestStats, duration: u64) -> Self {
+        Self {
+            requests: stats.total_requests(),
+            rps: stats.successful_requests() as f64 / duration.max(1) as f64,
+            p50: stats.percentile(50.0).unwrap_or_default(),
+            p95: stats.percentile(95.0).unwrap_or_default(),
+            p99: stats.percentile(99.0).unwrap_or_default(),
+            error_rate: stats.error_rate(),
+            bytes_per_sec: stats.phases().response_bytes() as f64 / duration.max(1) as f64,
         }
     }
 }
 
-impl fmt::Display for Stats {
+impl fmt::Display for Summary {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let requests_in_general = self.successful_requests + self.failed_requests;
+        write!(
+            f,
+            "requests {}, RPS {:.2}, p50 {} ms, p95 {} ms, p99 {} ms, error rate {:.4}",
+            self.requests, self.rps, self.p50, self.p95, self.p99, self.error_rate
+        )
+    }
+}
 
-        let mut min_response_time = u64::MAX;
-        let mut max_response_time = 0;
-        let mut sum = 0;
+#[derive(Serialize, Deserialize)]
+pub struct Stats {
+    overall: RequestStats,
+    error_codes: HashMap<u16, u64>,
+    methods: HashMap<String, RequestStats>,
+    // Stats received from another process start when they are received
+    #[serde(skip, default = "Instant::now")]
+    started: Instant,
+    time_series: TimeSeries,
+    validated_responses: u64,
+    #[serde(
+        serialize_with = "serialize_failure_counts",
+        deserialize_with = "deserialize_failure_counts"
+    )]
+    validation_failures: HashMap<ValidationFailure, u64>,
+    #[serde(default)]
+    proof_statuses: HashMap<ProofStatus, u64>,
+    // Proofs that could not be checked for reasons other than the response
+    #[serde(default)]
+    proof_check_errors: u64,
+    // Tree account fetches of proof validations by RPC node
+    #[serde(default)]
+    account_fetches: BTreeMap<String, AccountFetches>,
+    // Set if the run was interrupted before its planned duration
+    interrupted_after: Option<Duration>,
+    // Requests sent during the warm-up, not counted in the stats above
+    warm_up: Option<Box<Stats>>,
+    key_latencies: KeyLatencies,
+}
 
-        for time in self.response_time_millis.iter() {
-            if time < &min_response_time {
-                min_response_time = *time;
-            }
+impl Stats {
+    /// Creates stats whose time series starts at `started`, so stats of
+    /// workers of the same stage can be merged second by second
+    pub fn starting_at(started: Instant) -> Self {
+        Self {
+            overall: RequestStats::default(),
+            error_codes: HashMap::new(),
+            methods: HashMap::new(),
+            started,
+            time_series: TimeSeries::default(),
+            validated_responses: 0,
+            validation_failures: HashMap::new(),
+            proof_statuses: HashMap::new(),
+            proof_check_errors: 0,
+            account_fetches: BTreeMap::new(),
+            interrupted_after: None,
+            warm_up: None,
+            key_latencies: KeyLatencies::default(),
+        }
+    }
+
+    pub fn time_series(&self) -> &TimeSeries {
+        &self.time_series
+    }
+
+    fn current_second(&self) -> usize {
+        self.started.elapsed().as_secs() as usize
+    }
 
-            if time > &max_response_time {
-                max_response_time = *time;
+    pub fn merge(&mut self, other: Stats) {
+        self.overall.merge(&other.overall);
+        for (code, number) in other.error_codes {
+            *self.error_codes.entry(code).or_insert(0) += number;
+        }
+        for (method, method_stats) in other.methods {
+            self.methods.entry(method).or_default().merge(&method_stats);
+        }
+        self.time_series.merge(&other.time_series);
+        self.validated_responses += other.validated_responses;
+        for (failure, number) in other.validation_failures {
+            *self.validation_failures.entry(failure).or_insert(0) += number;
+        }
+        for (status, number) in other.proof_statuses {
+            *self.proof_statuses.entry(status).or_insert(0) += number;
+        }
+        self.proof_check_errors += other.proof_check_errors;
+        self.record_account_fetches(&other.account_fetches);
+        self.key_latencies.merge(other.key_latencies);
+        if let Some(other_warm_up) = other.warm_up {
+            match &mut self.warm_up {
+                Some(warm_up) => warm_up.merge(*other_warm_up),
+                None => self.warm_up = Some(other_warm_up),
             }
+        }
+    }
+
+    pub fn key_latencies(&self) -> &KeyLatencies {
+        &self.key_latencies
+    }
+
+    pub fn warm_up(&self) -> Option<&Stats> {
+        self.warm_up.as_deref()
+    }
+
+    pub fn interrupted_after(&self) -> Option<Duration> {
+        self.interrupted_after
+    }
+
+    pub fn mark_interrupted(&mut self, after: Duration) {
+        self.interrupted_after = Some(after);
+    }
+
+    /// Seconds the load actually ran for, `planned` unless interrupted
+    pub fn duration(&self, planned: u64) -> u64 {
+        self.interrupted_after
+            .map_or(planned, |duration| duration.as_secs())
+    }
+
+    pub fn merge_time_series(&mut self, time_series: &TimeSeries) {
+        self.time_series.merge(time_series);
+    }
+
+    pub fn overall(&self) -> &RequestStats {
+        &self.overall
+    }
+
+    pub fn methods(&self) -> &HashMap<String, RequestStats> {
+        &self.methods
+    }
+
+    pub fn record_success(&mut self, method: &str, time: u64) {
+        let second = self.current_second();
+        self.time_series.record(second, Some(time));
+        self.overall.record_success(time);
+        self.methods
+            .entry(method.to_string())
+            .or_default()
+            .record_success(time);
+    }
+
+    pub fn record_timings(&mut self, method: &str, timings: &RequestTimings) {
+        self.overall.phases.record(timings);
+        self.methods
+            .entry(method.to_string())
+            .or_default()
+            .phases
+            .record(timings);
+    }
+
+    /// Proofs that could not be checked are counted apart, the request is
+    /// not failed for them
+    pub fn record_valid_response(&mut self, proof_check: ProofCheck) {
+        self.validated_responses += 1;
+        match proof_check {
+            ProofCheck::Skipped => {}
+            ProofCheck::Valid(status) => *self.proof_statuses.entry(status).or_insert(0) += 1,
+            ProofCheck::Error => self.proof_check_errors += 1,
+        }
+    }
+
+    /// Responses that failed validation are counted as failed requests
+    pub fn record_invalid_response(&mut self, method: &str, failure: ValidationFailure) {
+        self.validated_responses += 1;
+        *self.validation_failures.entry(failure).or_insert(0) += 1;
+        if let ValidationFailure::Proof(status) = failure {
+            *self.proof_statuses.entry(status).or_insert(0) += 1;
+        }
+        self.record_failure(method, None);
+    }
+
+    pub fn record_account_fetches(&mut self, fetches: &BTreeMap<String, AccountFetches>) {
+        for (endpoint, endpoint_fetches) in fetches.iter() {
+            self.account_fetches
+                .entry(endpoint.clone())
+                .or_default()
+                .merge(endpoint_fetches);
+        }
+    }
 
-            sum += time;
+    pub fn record_failure(&mut self, method: &str, code: Option<u16>) {
+        let second = self.current_second();
+        self.time_series.record(second, None);
+        self.overall.record_failure();
+        if let Some(code) = code {
+            *self.error_codes.entry(code).or_insert(0) += 1;
         }
+        self.methods
+            .entry(method.to_string())
+            .or_default()
+            .record_failure();
+    }
+}
 
-        let average_response_time = sum / self.response_time_millis.len() as u64;
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let requests_in_general = self.overall.total_requests();
+
+        if let Some(duration) = self.interrupted_after {
+            write!(
+                f,
+                "\nRun was interrupted after {} s, results are partial\n",
+                duration.as_secs()
+            )?;
+        }
+
+        let response_times = &self.overall.response_times;
+        let average_response_time = response_times.mean().unwrap_or_default();
+        let max_response_time = response_times.max().unwrap_or_default();
+        let min_response_time = response_times.min().unwrap_or_default();
 
         write!(
             f,
             "\nNumber of requests sent: {}\nSuccessful: {}\nFailed: {}\n",
-            requests_in_general, self.successful_requests, self.failed_requests
+            requests_in_general,
+            self.overall.successful_requests(),
+            self.overall.failed_requests
         )?;
 
         write!(
@@ -112,18 +365,485 @@ impl fmt::Display for Stats {
             write!(f, "\n{} - {}", code, number)?;
         }
 
+        if self.validated_responses > 0 {
+            write!(
+                f,
+                "\n---\nValidated responses: {}\nInvalid responses:\nreason - number",
+                self.validated_responses
+            )?;
+            for (failure, number) in self.validation_failures.iter() {
+                write!(f, "\n{} - {}", failure, number)?;
+            }
+        }
+        if !self.proof_statuses.is_empty() {
+            write!(f, "\n---\nChecked proofs:\nstatus - number")?;
+            let statuses: BTreeMap<_, _> = self.proof_statuses.iter().collect();
+            for (status, number) in statuses {
+                write!(f, "\n{} - {}", status, number)?;
+            }
+        }
+        if self.proof_check_errors > 0 {
+            write!(
+                f,
+                "\n---\nProofs not checked because of account or getAsset errors: {}",
+                self.proof_check_errors
+            )?;
+        }
+        write!(f, "{}", AccountFetchesReport(&self.account_fetches))?;
+
+        if let Some(warm_up) = &self.warm_up {
+            write!(f, "\n---\nWarm-up, excluded from the results:\n{}", warm_up)?;
+        }
+
         Ok(())
     }
 }
 
-pub struct Worker {
+/// Builds the request for a method of the keys file and one of its keys
+pub fn build_body(
+    command: &str,
+    arg_key: String,
+    distribution: &ParamsDistribution,
+    rnd: &mut impl Rng,
+) -> Body {
+    match command {
+        GET_ASSET_METHOD => Body::new(GET_ASSET_METHOD, json!(generate_get_asset_params(arg_key))),
+        GET_ASSET_PROOF_METHOD => Body::new(
+            GET_ASSET_PROOF_METHOD,
+            json!(generate_get_asset_proof_params(arg_key)),
+        ),
+        GET_ASSET_PROOFS_METHOD | GET_ASSET_PROOF_BATCH_METHOD => Body::new(
+            command,
+            json!(generate_get_asset_proofs_params(parse_key_batch(&arg_key))),
+        ),
+        GET_ASSET_BY_OWNER_METHOD => Body::new(
+            GET_ASSET_BY_OWNER_METHOD,
+            json!(generate_get_assets_by_owner_params(
+                arg_key,
+                None,
+                None,
+                distribution,
+                rnd
+            )),
+        ),
+        GET_ASSET_BY_AUTHORITY_METHOD => Body::new(
+            GET_ASSET_BY_AUTHORITY_METHOD,
+            json!(generate_get_assets_by_authority_params(
+                arg_key,
+                None,
+                None,
+                distribution,
+                rnd
+            )),
+        ),
+        GET_ASSET_BY_GROUP_METHOD => Body::new(
+            GET_ASSET_BY_GROUP_METHOD,
+            json!(generate_get_assets_by_group_params(
+                arg_key,
+                None,
+                None,
+                distribution,
+                rnd
+            )),
+        ),
+        GET_ASSET_BY_CREATOR_METHOD => Body::new(
+            GET_ASSET_BY_CREATOR_METHOD,
+            json!(generate_get_assets_by_creator_params(
+                arg_key,
+                None,
+                None,
+                distribution,
+                rnd
+            )),
+        ),
+        GET_TOKEN_ACCOUNTS_BY_OWNER => Body::new(
+            GET_TOKEN_ACCOUNTS,
+            json!(generate_get_token_accounts(
+                Some(arg_key),
+                None,
+                distribution,
+                rnd
+            )),
+        ),
+        GET_TOKEN_ACCOUNTS_BY_MINT => Body::new(
+            GET_TOKEN_ACCOUNTS,
+            json!(generate_get_token_accounts(
+                None,
+                Some(arg_key),
+                distribution,
+                rnd
+            )),
+        ),
+        GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT => {
+            let owner_mint: Vec<String> = arg_key
+                .trim_matches(|c| c == '(' || c == ')')
+                .split(';')
+                .map(String::from)
+                .collect();
+
+            Body::new(
+                GET_TOKEN_ACCOUNTS,
+                json!(generate_get_token_accounts(
+                    Some(owner_mint[0].clone()),
+                    Some(owner_mint[1].clone()),
+                    distribution,
+                    rnd
+                )),
+            )
+        }
+        GET_SIGNATURES_FOR_ASSET => Body::new(
+            GET_SIGNATURES_FOR_ASSET,
+            json!(generate_get_signatures_for_asset(
+                arg_key,
+                distribution,
+                rnd
+            )),
+        ),
+        _ => {
+            panic!("Unknown command was passed")
+        }
+    }
+}
+
+/// Request of a load test with the keys file method and key it was built from
+#[derive(Clone)]
+pub struct LoadRequest {
+    pub method: String,
+    pub key: String,
+    pub body: Body,
+    // Pause before sending the request
+    pub think_time: Duration,
+}
+
+/// Source of the requests a worker sends
+pub enum RequestGenerator {
+    Random {
+        keys_fetcher: Box<FileKeysFetcher>,
+        params_distributions: Arc<HashMap<String, ParamsDistribution>>,
+    },
+    Sequence {
+        requests: Arc<Vec<LoadRequest>>,
+        position: usize,
+        // Drives the sampling of responses to validate
+        rnd: Box<StdRng>,
+    },
+    Journeys(Box<Journeys>),
+}
+
+impl RequestGenerator {
+    pub fn next_request(&mut self) -> LoadRequest {
+        match self {
+            RequestGenerator::Random {
+                keys_fetcher,
+                params_distributions,
+            } => {
+                let (command, arg_key) = keys_fetcher.get_random_command();
+                let distribution = params_distributions
+                    .get(&command)
+                    .cloned()
+                    .unwrap_or_default();
+                let body = build_body(&command, arg_key.clone(), &distribution, keys_fetcher.rnd());
+
+                LoadRequest {
+                    method: command,
+                    key: arg_key,
+                    body,
+                    think_time: Duration::ZERO,
+                }
+            }
+            RequestGenerator::Sequence {
+                requests, position, ..
+            } => {
+                let request = requests[*position % requests.len()].clone();
+                *position += 1;
+
+                request
+            }
+            RequestGenerator::Journeys(journeys) => journeys.next_request(),
+        }
+    }
+
+    /// Seeded random generator of the worker
+    pub fn rnd(&mut self) -> &mut StdRng {
+        match self {
+            RequestGenerator::Random { keys_fetcher, .. } => keys_fetcher.rnd(),
+            RequestGenerator::Sequence { rnd, .. } => rnd,
+            RequestGenerator::Journeys(journeys) => journeys.rnd(),
+        }
+    }
+
+    /// Passes the response to the last request on, `None` if it failed
+    pub fn record_response(&mut self, response: Option<&Value>) {
+        if let RequestGenerator::Journeys(journeys) = self {
+            journeys.record_response(response);
+        }
+    }
+}
+
+/// Keys and request parameters shared by all workers of a load test
+pub struct LoadProfile {
+    // Drives the choice of methods and keys, worker `i` uses `seed + i`
+    seed: u64,
+    keys_fetcher: FileKeysFetcher,
+    params_distributions: Arc<HashMap<String, ParamsDistribution>>,
+    // Fixed requests replayed in a loop instead of generating new ones
+    sequence: Option<Arc<Vec<LoadRequest>>>,
+    // Scenarios with the weighted choice between them, replace random requests
+    scenarios: Option<(Arc<Vec<Scenario>>, WeightedIndex<u32>)>,
+    validator: Option<Arc<ResponseValidator>>,
+    warm_up: Duration,
+    stop_timeout: Duration,
+}
+
+impl LoadProfile {
+    pub async fn from_config(
+        config: &IntegrityVerificationConfig,
+    ) -> Result<Self, IntegrityVerificationError> {
+        let keys_fetcher = FileKeysFetcher::new(&config.testing_file_path)
+            .await
+            .map_err(IntegrityVerificationError::FetchKeys)?;
+
+        Self::with_keys(config, keys_fetcher)
+    }
+
+    /// Creates the profile for keys that were not read from the keys file of
+    /// `config`
+    pub fn with_keys(
+        config: &IntegrityVerificationConfig,
+        mut keys_fetcher: FileKeysFetcher,
+    ) -> Result<Self, IntegrityVerificationError> {
+        if !config.method_weights.is_empty() {
+            keys_fetcher
+                .set_method_weights(&config.method_weights)
+                .map_err(IntegrityVerificationError::FetchKeys)?;
+        }
+        keys_fetcher
+            .set_key_selection(config.key_selection, config.zipf_exponent)
+            .map_err(IntegrityVerificationError::FetchKeys)?;
+
+        let scenarios = if config.scenarios.is_empty() {
+            None
+        } else {
+            for step in config.scenarios.iter().flat_map(|scenario| &scenario.steps) {
+                if step.key_from.is_none() && !keys_fetcher.keys_map.contains_key(&step.method) {
+                    return Err(IntegrityVerificationError::FetchKeys(format!(
+                        "No keys for scenario method {}",
+                        step.method
+                    )));
+                }
+            }
+            let choice =
+                WeightedIndex::new(config.scenarios.iter().map(|scenario| scenario.weight))
+                    .map_err(|e| IntegrityVerificationError::ValidateConfig(e.to_string()))?;
+            Some((Arc::new(config.scenarios.clone()), choice))
+        };
+
+        Ok(Self {
+            seed: config.seed.unwrap_or_else(rand::random),
+            keys_fetcher,
+            params_distributions: Arc::new(config.method_params.clone()),
+            sequence: None,
+            scenarios,
+            validator: ResponseValidator::from_config(config).map(Arc::new),
+            warm_up: Duration::from_secs(config.warm_up_duration),
+            stop_timeout: Duration::from_secs(config.stop_timeout),
+        })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn key_selection(&self) -> KeySelection {
+        self.keys_fetcher.key_selection()
+    }
+
+    pub fn keys(&self) -> &HashMap<String, Vec<String>> {
+        &self.keys_fetcher.keys_map
+    }
+
+    pub fn warm_up(&self) -> Duration {
+        self.warm_up
+    }
+
+    /// Generates `length` requests up front, so every stage run with this
+    /// profile sends exactly the same requests in the same order
+    pub fn with_fixed_sequence(mut self, length: usize) -> Self {
+        let mut generator = RequestGenerator::Random {
+            keys_fetcher: Box::new(self.keys_fetcher.fork(self.seed)),
+            params_distributions: self.params_distributions.clone(),
+        };
+        let requests = (0..length).map(|_| generator.next_request()).collect();
+        self.sequence = Some(Arc::new(requests));
+
+        self
+    }
+
+    pub fn worker_requests(&self, worker_id: usize, num_of_workers: usize) -> RequestGenerator {
+        let seed = self.seed.wrapping_add(worker_id as u64);
+        match &self.sequence {
+            // Workers start at evenly spread offsets of the shared sequence
+            Some(requests) => RequestGenerator::Sequence {
+                requests: requests.clone(),
+                position: worker_id * requests.len() / num_of_workers.max(1),
+                rnd: Box::new(StdRng::seed_from_u64(seed)),
+            },
+            None => match &self.scenarios {
+                Some((scenarios, choice)) => RequestGenerator::Journeys(Box::new(Journeys::new(
+                    scenarios.clone(),
+                    choice.clone(),
+                    self.keys_fetcher.fork(seed),
+                    self.params_distributions.clone(),
+                    seed,
+                ))),
+                None => RequestGenerator::Random {
+                    keys_fetcher: Box::new(self.keys_fetcher.fork(seed)),
+                    params_distributions: self.params_distributions.clone(),
+                },
+            },
+        }
+    }
+}
+
+/// Sends the requests of a single worker and records their outcome
+struct RequestSender {
+    api_endpoint: String,
+    requests: RequestGenerator,
+    api: IntegrityVerificationApi,
+    stat: Stats,
+    // Requests started before `measured_from` go to the warm-up stats
+    warm_up_stat: Option<Stats>,
+    measured_from: Instant,
+    gauges: Arc<LoadGauges>,
+    validator: Option<Arc<ResponseValidator>>,
+}
+
+impl RequestSender {
+    async fn send(&mut self, request: LoadRequest) {
+        let (command, body) = (&request.method, &request.body);
+
+        let start = Instant::now();
+        self.gauges.inc_in_flight();
+        let api_call_result = self
+            .api
+            .make_timed_request(&self.api_endpoint, &json!(body).to_string())
+            .await;
+        self.gauges.dec_in_flight();
+
+        let response_time = start.elapsed().as_millis() as u64;
+
+        let validation = match (&api_call_result, &self.validator) {
+            (Ok((response, _)), Some(validator))
+                if validator.should_validate(self.requests.rnd()) =>
+            {
+                Some(validator.validate(&self.api_endpoint, body, response).await)
+            }
+            _ => None,
+        };
+
+        let response = match (&api_call_result, &validation) {
+            (Ok((response, _)), None | Some(Ok(_))) => Some(response),
+            _ => None,
+        };
+        self.requests.record_response(response);
+
+        let stat = match &mut self.warm_up_stat {
+            Some(warm_up_stat) if start < self.measured_from => warm_up_stat,
+            _ => &mut self.stat,
+        };
+        if let Ok((_, timings)) = &api_call_result {
+            stat.record_timings(command, timings);
+        }
+        let outcome = match api_call_result {
+            Ok(_) => match validation {
+                Some(Err(failure)) => {
+                    stat.record_invalid_response(command, failure);
+                    Cow::Owned(format!("invalid: {}", failure))
+                }
+                Some(Ok(proof_check)) => {
+                    stat.record_valid_response(proof_check);
+                    stat.record_success(command, response_time);
+                    Cow::Borrowed("ok")
+                }
+                None => {
+                    stat.record_success(command, response_time);
+                    Cow::Borrowed("ok")
+                }
+            },
+            Err(IntegrityVerificationError::ResponseStatusCode(code)) => {
+                stat.record_failure(command, Some(code));
+                Cow::Owned(format!("status {}", code))
+            }
+            Err(err) => {
+                stat.record_failure(command, None);
+                Cow::Owned(err.to_string())
+            }
+        };
+        stat.key_latencies
+            .record(command, &request.key, &body.params, response_time, outcome);
+    }
+}
+
+/// Command handling part of a worker, kept apart from the request sender so
+/// commands can be read while a request is in flight
+struct WorkerControl {
     id: u32,
     commands_channel: Receiver<Commands>,
-    api_endpoint: String,
     active: bool,
-    keys_fetcher: FileKeysFetcher,
-    api: IntegrityVerificationApi,
-    stat: Arc<Mutex<Stats>>,
+    aborted: bool,
+    gauges: Arc<LoadGauges>,
+}
+
+impl WorkerControl {
+    /// Reads the latest command, returns `true` if the worker has to stop
+    fn handle_command(&mut self) -> bool {
+        match &*self.commands_channel.borrow_and_update() {
+            Commands::Init => false,
+            Commands::Start(ids) => {
+                if ids.contains(&self.id) && !self.active {
+                    info!("Worker #{} is starting it's job", self.id);
+                    self.active = true;
+                    self.gauges.inc_active_workers();
+                }
+                false
+            }
+            Commands::Stop(ids) => ids.contains(&self.id),
+            Commands::Abort(ids) => {
+                self.aborted = ids.contains(&self.id);
+                self.aborted
+            }
+        }
+    }
+
+    /// Awaits `future` while handling commands, returns `true` if the worker
+    /// has to stop before `future` is done
+    async fn until_stopped<F: Future>(&mut self, mut future: Pin<&mut F>) -> bool {
+        loop {
+            tokio::select! {
+                _ = &mut future => return false,
+                stop = self.next_command() => {
+                    if stop {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits for the next command, returns `true` if the worker has to stop
+    async fn next_command(&mut self) -> bool {
+        if self.commands_channel.changed().await.is_err() {
+            info!("Cannot read data from channel");
+            self.aborted = true;
+            return true;
+        }
+        self.handle_command()
+    }
+}
+
+pub struct Worker {
+    control: WorkerControl,
+    sender: RequestSender,
 }
 
 impl Worker {
@@ -131,183 +851,330 @@ impl Worker {
         id: u32,
         commands_channel: Receiver<Commands>,
         api_endpoint: String,
-        keys_fetcher: FileKeysFetcher,
-        stat: Arc<Mutex<Stats>>,
+        profile: &LoadProfile,
+        num_of_workers: usize,
+        started: Instant,
+        gauges: Arc<LoadGauges>,
     ) -> Self {
+        let measured_from = started + profile.warm_up;
         Self {
-            id,
-            commands_channel,
-            api_endpoint,
-            active: false,
-            keys_fetcher,
-            api: IntegrityVerificationApi::new(),
-            stat,
+            control: WorkerControl {
+                id,
+                commands_channel,
+                active: false,
+                aborted: false,
+                gauges: gauges.clone(),
+            },
+            sender: RequestSender {
+                api_endpoint,
+                requests: profile.worker_requests(id as usize, num_of_workers),
+                api: IntegrityVerificationApi::new(),
+                stat: Stats::starting_at(measured_from),
+                warm_up_stat: (!profile.warm_up.is_zero()).then(|| Stats::starting_at(started)),
+                measured_from,
+                gauges,
+                validator: profile.validator.clone(),
+            },
         }
     }
 
-    pub async fn run(&mut self) {
-        let mut counter = 5;
+    /// Sends requests while active and returns the collected statistics once
+    /// stopped. Statistics are local to the worker, so nothing is shared
+    /// between workers while the load is running
+    pub async fn run(mut self) -> Stats {
+        let control = &mut self.control;
+        info!("Worker #{} is initialised...", control.id);
 
         loop {
-            if let Ok(has_changed) = self.commands_channel.has_changed() {
-                if has_changed {
-                    let msg = self.commands_channel.borrow_and_update();
-
-                    match &(*msg) {
-                        Commands::Init => {
-                            info!("Worker #{} is initialised...", self.id);
-                        }
-                        Commands::Start(ids) => {
-                            for id in ids.iter() {
-                                if id == &self.id {
-                                    info!("Worker #{} is starting it's job", self.id);
-                                    self.active = true;
-                                    break;
-                                }
-                            }
-                        }
-                        Commands::Stop(ids) => {
-                            for id in ids.iter() {
-                                if id == &self.id {
-                                    return;
-                                }
-                            }
-                        }
-                    }
+            if !control.active {
+                if control.next_command().await {
+                    break;
                 }
-            } else {
-                if counter == 0 {
-                    info!("Cannot read data from channel");
-                    return;
-                }
-                counter -= 1;
+                continue;
+            }
 
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            let request = self.sender.requests.next_request();
+            if !request.think_time.is_zero() {
+                let think_time = tokio::time::sleep(request.think_time);
+                tokio::pin!(think_time);
+                if control.until_stopped(think_time).await {
+                    break;
+                }
             }
 
-            if self.active {
-                debug!("Worker #{} is sending API request", self.id);
-                let (command, arg_key) = self.keys_fetcher.get_random_command();
-
-                let body = {
-                    match command.as_ref() {
-                        GET_ASSET_METHOD => {
-                            Body::new(GET_ASSET_METHOD, json!(generate_get_asset_params(arg_key)))
-                        }
-                        GET_ASSET_PROOF_METHOD => Body::new(
-                            GET_ASSET_PROOF_METHOD,
-                            json!(generate_get_asset_proof_params(arg_key)),
-                        ),
-                        GET_ASSET_BY_OWNER_METHOD => Body::new(
-                            GET_ASSET_BY_OWNER_METHOD,
-                            json!(generate_get_assets_by_owner_params(arg_key, None, None)),
-                        ),
-                        GET_ASSET_BY_AUTHORITY_METHOD => Body::new(
-                            GET_ASSET_BY_AUTHORITY_METHOD,
-                            json!(generate_get_assets_by_authority_params(arg_key, None, None)),
-                        ),
-                        GET_ASSET_BY_GROUP_METHOD => Body::new(
-                            GET_ASSET_BY_GROUP_METHOD,
-                            json!(generate_get_assets_by_group_params(arg_key, None, None)),
-                        ),
-                        GET_ASSET_BY_CREATOR_METHOD => Body::new(
-                            GET_ASSET_BY_CREATOR_METHOD,
-                            json!(generate_get_assets_by_creator_params(arg_key, None, None)),
-                        ),
-                        GET_TOKEN_ACCOUNTS_BY_OWNER => Body::new(
-                            GET_TOKEN_ACCOUNTS,
-                            json!(generate_get_token_accounts(Some(arg_key), None)),
-                        ),
-                        GET_TOKEN_ACCOUNTS_BY_MINT => Body::new(
-                            GET_TOKEN_ACCOUNTS,
-                            json!(generate_get_token_accounts(None, Some(arg_key))),
-                        ),
-                        GET_TOKEN_ACCOUNTS_BY_OWNER_AND_MINT => {
-                            let owner_mint: Vec<String> = arg_key
-                                .trim_matches(|c| c == '(' || c == ')')
-                                .split(';')
-                                .map(String::from)
-                                .collect();
-
-                            Body::new(
-                                GET_TOKEN_ACCOUNTS,
-                                json!(generate_get_token_accounts(
-                                    Some(owner_mint[0].clone()),
-                                    Some(owner_mint[1].clone())
-                                )),
-                            )
-                        }
-                        GET_SIGNATURES_FOR_ASSET => Body::new(
-                            GET_SIGNATURES_FOR_ASSET,
-                            json!(generate_get_signatures_for_asset(arg_key)),
-                        ),
-                        _ => {
-                            panic!("Unknown command was passed")
-                        }
-                    }
-                };
-
-                let start = tokio::time::Instant::now();
-                let api_call_result = self
-                    .api
-                    .make_request(&self.api_endpoint, &json!(body).to_string())
-                    .await;
-
-                if let Err(e) = api_call_result {
-                    let mut stat = self.stat.lock().await;
-
-                    if let IntegrityVerificationError::ResponseStatusCode(code) = e {
-                        stat.inc_failed_requests();
-                        stat.inc_error_code(code);
-                    } else {
-                        stat.inc_failed_requests();
+            debug!("Worker #{} is sending API request", control.id);
+            let request = self.sender.send(request);
+            tokio::pin!(request);
+
+            // Commands are handled while the request is in flight
+            if control.until_stopped(request.as_mut()).await {
+                // A stopped worker still waits for its last response unless
+                // it is aborted
+                while !control.aborted {
+                    tokio::select! {
+                        _ = &mut request => break,
+                        _ = control.next_command() => {}
                     }
-                } else {
-                    let mut stat = self.stat.lock().await;
-                    stat.add_response_time(start.elapsed().as_millis() as u64);
-                    stat.inc_successful_requests();
                 }
+                if control.aborted {
+                    warn!("Worker #{} dropped its in-flight request", control.id);
+                }
+                break;
             }
         }
+
+        if control.active {
+            control.gauges.dec_active_workers();
+        }
+        let mut stat = self.sender.stat;
+        stat.warm_up = self.sender.warm_up_stat.map(Box::new);
+        stat
     }
 }
 
+/// Samples the gauges in the middle of every second until cancelled
+pub async fn sample_gauges(
+    started: Instant,
+    gauges: Arc<LoadGauges>,
+    cancel_token: CancellationToken,
+) -> TimeSeries {
+    let mut time_series = TimeSeries::default();
+    let mut interval =
+        tokio::time::interval_at(started + Duration::from_millis(500), Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                time_series.sample_gauges(started.elapsed().as_secs() as usize, &gauges);
+            }
+            _ = cancel_token.cancelled() => break,
+        }
+    }
+    time_series
+}
+
 pub async fn run_performance_tests(
-    num_of_threads: usize,
-    test_duration: u64,
-    api_url: String,
-    testing_file_path: String,
-) {
-    let (tx, rx) = watch::channel(Commands::Init);
+    config: &IntegrityVerificationConfig,
+    results_path: Option<&str>,
+    baseline_path: Option<&str>,
+    cancel_token: CancellationToken,
+) -> Result<(), IntegrityVerificationError> {
+    let profile = LoadProfile::from_config(config).await?;
+    let stat = run_load_stage(
+        config.num_of_virtual_users,
+        config.test_duration_time,
+        &config.testing_host,
+        &profile,
+        &cancel_token,
+    )
+    .await;
 
-    let stat = Arc::new(Mutex::new(Stats::new()));
+    report_performance_results(config, &profile, &stat, results_path, baseline_path)
+}
 
-    let mut set = JoinSet::new();
-    for id in 0..num_of_threads {
-        let keys_fetcher = FileKeysFetcher::new(&testing_file_path.clone())
-            .await
-            .unwrap();
-        let rx = rx.clone();
-        let stat = stat.clone();
-        let api_url = api_url.clone();
-        set.spawn(async move {
-            let mut worker = Worker::new(id as u32, rx, api_url, keys_fetcher, stat);
+/// Prints and exports the statistics of a performance run, then saves them
+/// and compares them with the baseline if requested
+pub fn report_performance_results(
+    config: &IntegrityVerificationConfig,
+    profile: &LoadProfile,
+    stat: &Stats,
+    results_path: Option<&str>,
+    baseline_path: Option<&str>,
+) -> Result<(), IntegrityVerificationError> {
+    println!("Key selection: {}", profile.key_selection());
+    println!("{}", stat);
+    println!(
+        "{}",
+        TimingBreakdown {
+            stats: stat,
+            duration: stat.duration(config.test_duration_time),
+        }
+    );
+    if config.slowest_keys_report_size > 0 {
+        println!(
+            "{}",
+            SlowestKeysReport {
+                keys: stat
+                    .key_latencies()
+                    .slowest(config.slowest_keys_report_size),
+            }
+        );
+    }
+
+    stat.time_series().export(
+        config.time_series_csv_path.as_deref(),
+        config.time_series_json_path.as_deref(),
+    )?;
+
+    let results = PerformanceResults::new(config, profile, stat)?;
+    if let Some(path) = results_path {
+        results.save(path)?;
+    }
+    if let Some(path) = baseline_path {
+        if results.partial {
+            warn!("Run was interrupted, skipping comparison with baseline");
+        } else {
+            compare_with_baseline(config, &results, path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Workers of a load stage, sending requests until the stage is stopped
+pub struct LoadStage {
+    commands: watch::Sender<Commands>,
+    ids: Vec<u32>,
+    measured_from: Instant,
+    stop_timeout: Duration,
+    workers: JoinSet<Stats>,
+    sampler: JoinHandle<TimeSeries>,
+    sampler_token: CancellationToken,
+    // Validator shared with earlier stages and its account fetches when this
+    // stage started, so only the fetches of this stage are reported
+    validator: Option<Arc<ResponseValidator>>,
+    account_fetches_at_start: BTreeMap<String, AccountFetches>,
+}
+
+impl LoadStage {
+    /// Starts workers `ids` against `api_url`, measuring begins once the
+    /// warm-up of `profile` is over
+    pub fn start(ids: Vec<u32>, api_url: &str, profile: &LoadProfile) -> Self {
+        let (tx, rx) = watch::channel(Commands::Init);
+
+        let started = Instant::now();
+        let measured_from = started + profile.warm_up;
+        let gauges = Arc::new(LoadGauges::default());
+        let sampler_token = CancellationToken::new();
+        let sampler = tokio::spawn(sample_gauges(
+            measured_from,
+            gauges.clone(),
+            sampler_token.clone(),
+        ));
 
-            worker.run().await;
+        let mut workers = JoinSet::new();
+        for id in ids.iter() {
+            let worker = Worker::new(
+                *id,
+                rx.clone(),
+                api_url.to_string(),
+                profile,
+                ids.len(),
+                started,
+                gauges.clone(),
+            );
+            workers.spawn(worker.run());
+        }
+
+        tx.send(Commands::Start(ids.clone())).unwrap();
 
-            Ok(())
-        });
+        Self {
+            commands: tx,
+            ids,
+            measured_from,
+            stop_timeout: profile.stop_timeout,
+            workers,
+            sampler,
+            sampler_token,
+            validator: profile.validator.clone(),
+            account_fetches_at_start: profile
+                .validator
+                .as_ref()
+                .map(|validator| validator.account_fetches())
+                .unwrap_or_default(),
+        }
     }
 
-    let ids: Vec<usize> = (0..num_of_threads).collect();
-    let ids: Vec<u32> = ids.iter().map(|x| *x as u32).collect();
-    tx.send(Commands::Start(ids.clone())).unwrap();
+    pub fn measured_from(&self) -> Instant {
+        self.measured_from
+    }
 
-    tokio::time::sleep(tokio::time::Duration::from_secs(test_duration)).await;
+    /// Stops the workers and returns their merged statistics
+    pub async fn stop(mut self) -> Stats {
+        self.commands
+            .send(Commands::Stop(self.ids.clone()))
+            .unwrap();
 
-    tx.send(Commands::Stop(ids)).unwrap();
+        // Workers' stats are merged only once the stage is over
+        let mut stat = Stats::starting_at(self.measured_from);
+        let stop_deadline = tokio::time::sleep(self.stop_timeout);
+        tokio::pin!(stop_deadline);
+        loop {
+            let worker_stat = tokio::select! {
+                worker_stat = self.workers.join_next() => worker_stat,
+                _ = &mut stop_deadline, if !stop_deadline.is_elapsed() => {
+                    warn!(
+                        "In-flight requests did not finish within {} s, aborting them",
+                        self.stop_timeout.as_secs()
+                    );
+                    self.commands
+                        .send(Commands::Abort(self.ids.clone()))
+                        .unwrap();
+                    continue;
+                }
+            };
+            match worker_stat {
+                Some(Ok(worker_stat)) => stat.merge(worker_stat),
+                Some(Err(err)) if err.is_panic() => {
+                    let err = err.into_panic();
+                    error!("Task panic: {:?}", err);
+                }
+                Some(Err(err)) => {
+                    error!("Task error: {}", err);
+                }
+                None => break,
+            }
+        }
+
+        self.sampler_token.cancel();
+        match self.sampler.await {
+            Ok(gauges_series) => stat.merge_time_series(&gauges_series),
+            Err(err) => error!("Gauges sampler error: {}", err),
+        }
+        if let Some(validator) = &self.validator {
+            let fetches = validator
+                .account_fetches()
+                .into_iter()
+                .map(|(endpoint, fetches)| {
+                    let earlier = self
+                        .account_fetches_at_start
+                        .get(&endpoint)
+                        .copied()
+                        .unwrap_or_default();
+                    (endpoint, fetches.since(&earlier))
+                })
+                .collect();
+            stat.record_account_fetches(&fetches);
+        }
+
+        stat
+    }
+}
+
+/// Runs `num_of_threads` workers against `api_url` for the warm-up of `profile`
+/// followed by `test_duration` seconds and returns the statistics they
+/// collected. Cancelling `cancel_token` stops the workers early, the returned
+/// stats are then marked as interrupted
+pub async fn run_load_stage(
+    num_of_threads: usize,
+    test_duration: u64,
+    api_url: &str,
+    profile: &LoadProfile,
+    cancel_token: &CancellationToken,
+) -> Stats {
+    let ids = (0..num_of_threads as u32).collect();
+    let stage = LoadStage::start(ids, api_url, profile);
+    let measured_from = stage.measured_from();
+
+    let interrupted_after = tokio::select! {
+        _ = tokio::time::sleep_until(measured_from + Duration::from_secs(test_duration)) => None,
+        _ = cancel_token.cancelled() => {
+            warn!("Load was interrupted, stopping workers");
+            Some(Instant::now().saturating_duration_since(measured_from))
+        }
+    };
 
-    graceful_stop(&mut set).await;
+    let mut stat = stage.stop().await;
+    stat.interrupted_after = interrupted_after;
 
-    println!("{}", stat.lock().await);
+    stat
 }
@@ -0,0 +1,335 @@
+use crate::account_provider::{account_provider_from_config, AccountFetches};
+use crate::api::IntegrityVerificationApi;
+use crate::compressed_leaf::check_asset_leaf;
+use crate::config::IntegrityVerificationConfig;
+use crate::diff_checker::GET_ASSET_METHOD;
+use crate::error::IntegrityVerificationError;
+use crate::interfaces::AccountProvider;
+use crate::merkle_tree::{ProofLength, RootMatch, TreeAccount};
+use crate::params_generation::generate_get_asset_params;
+use crate::requests::Body;
+use crate::tree_cache::{CachedTree, TreeCache};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Outcome of checking a `getAssetProof` response against the tree account
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ProofStatus {
+    // Proof hashes to the returned root, which is the current root of the tree
+    ValidCurrent,
+    // Proof hashes to the returned root, which is still in the changelog buffer
+    ValidRecent,
+    // Proof hashes to the returned root, which the tree does not know
+    Stale,
+    // Proof does not hash to the returned root
+    Inconsistent,
+    // Leaf is not the one of the asset data `getAsset` returns
+    LeafMismatch,
+    // Fewer proof nodes than the tree needs with its canopy
+    TruncatedProof,
+    // More proof nodes than the tree has levels
+    OverlongProof,
+    // Proof of the current root whose nodes inside the canopy differ from it
+    CanopyMismatch,
+    // Proof with a node that is not a base58 encoded 32 bytes hash
+    MalformedProof,
+}
+
+impl ProofStatus {
+    pub fn is_valid(&self) -> bool {
+        matches!(self, ProofStatus::ValidCurrent | ProofStatus::ValidRecent)
+    }
+}
+
+impl fmt::Display for ProofStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            ProofStatus::ValidCurrent => "valid current",
+            ProofStatus::ValidRecent => "valid recent",
+            ProofStatus::Stale => "stale",
+            ProofStatus::Inconsistent => "inconsistent",
+            ProofStatus::LeafMismatch => "leaf mismatch",
+            ProofStatus::TruncatedProof => "truncated proof",
+            ProofStatus::OverlongProof => "over-long proof",
+            ProofStatus::CanopyMismatch => "canopy mismatch",
+            ProofStatus::MalformedProof => "malformed proof",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+fn parse_node(response: &Value, field: &str) -> Result<[u8; 32], IntegrityVerificationError> {
+    let node = response["result"][field].as_str().ok_or(
+        IntegrityVerificationError::CannotGetResponseField(field.to_string()),
+    )?;
+    Ok(Pubkey::from_str(node)?.to_bytes())
+}
+
+pub struct ProofValidator {
+    api: IntegrityVerificationApi,
+    trees: TreeCache,
+}
+
+impl ProofValidator {
+    pub fn new(config: &IntegrityVerificationConfig) -> Self {
+        Self::with_account_provider(
+            account_provider_from_config(config),
+            Duration::from_millis(config.tree_cache_max_age_millis),
+        )
+    }
+
+    pub fn with_account_provider(
+        account_provider: Box<dyn AccountProvider + Send + Sync>,
+        tree_max_age: Duration,
+    ) -> Self {
+        Self {
+            api: IntegrityVerificationApi::new(),
+            trees: TreeCache::new(account_provider, tree_max_age),
+        }
+    }
+
+    /// Returns the tree account fetches sent to every RPC node so far
+    pub fn account_fetches(&self) -> BTreeMap<String, AccountFetches> {
+        self.trees.account_fetches()
+    }
+
+    /// Checks the `getAssetProof` response against the on-chain tree and the
+    /// asset data `getAsset` of `host` returns
+    pub async fn check_proof_valid(
+        &self,
+        host: &str,
+        asset_id: &str,
+        response: &Value,
+    ) -> Result<ProofStatus, IntegrityVerificationError> {
+        let tree_id = Pubkey::new_from_array(parse_node(response, "tree_id")?);
+        let get_asset_req = json!(&Body::new(
+            GET_ASSET_METHOD,
+            json!(generate_get_asset_params(asset_id.to_string()))
+        ))
+        .to_string();
+        // Neither read depends on the other, the asset is requested while the
+        // tree account is fetched
+        let (get_asset, tree) = tokio::join!(
+            self.api.make_request(host, &get_asset_req),
+            self.trees.get(&tree_id)
+        );
+
+        self.check_proof_with_tree(response, &get_asset?, &tree_id, tree?)
+            .await
+    }
+
+    /// Checks that the leaf of the `getAssetProof` response is the one of the
+    /// `getAsset` response, and the proof against its own `root` and the roots
+    /// of the tree account of the account provider
+    pub async fn check_proof(
+        &self,
+        response: &Value,
+        get_asset: &Value,
+    ) -> Result<ProofStatus, IntegrityVerificationError> {
+        let tree_id = Pubkey::new_from_array(parse_node(response, "tree_id")?);
+        let tree = self.trees.get(&tree_id).await?;
+        self.check_proof_with_tree(response, get_asset, &tree_id, tree)
+            .await
+    }
+
+    /// Same as `check_proof` with the tree account already read from the
+    /// cache, along with whether it was read before
+    async fn check_proof_with_tree(
+        &self,
+        response: &Value,
+        get_asset: &Value,
+        tree_id: &Pubkey,
+        (tree, cached): (Arc<CachedTree>, bool),
+    ) -> Result<ProofStatus, IntegrityVerificationError> {
+        let leaf = parse_node(response, "leaf")?;
+        let root = parse_node(response, "root")?;
+        let leaf_index = get_asset["result"]["compression"]["leaf_id"]
+            .as_u64()
+            .ok_or(IntegrityVerificationError::CannotGetResponseField(
+                "leaf_id".to_string(),
+            ))? as u32;
+
+        if let Some(mismatch) = check_asset_leaf(get_asset, &leaf)? {
+            warn!(
+                "Asset {} does not match its {}",
+                get_asset["result"]["id"], mismatch
+            );
+            return Ok(ProofStatus::LeafMismatch);
+        }
+
+        let proof = response["result"]["proof"]
+            .as_array()
+            .ok_or(IntegrityVerificationError::CannotGetResponseField(
+                "proof".to_string(),
+            ))?
+            .iter()
+            .map(|node| {
+                node.as_str()
+                    .and_then(|node| Pubkey::from_str(node).ok())
+                    .map(|node| node.to_bytes())
+            })
+            .collect::<Option<Vec<_>>>();
+        let Some(proof) = proof else {
+            return Ok(ProofStatus::MalformedProof);
+        };
+
+        let status = Self::check_against_tree(&tree.data, leaf, &proof, root, leaf_index)?;
+        // The tree may have changed since it was cached, a proof failing
+        // against it is checked again against the latest version
+        if cached && matches!(status, ProofStatus::Stale | ProofStatus::Inconsistent) {
+            let tree = self.trees.refresh(tree_id, &tree).await?;
+            return Self::check_against_tree(&tree.data, leaf, &proof, root, leaf_index);
+        }
+        Ok(status)
+    }
+
+    fn check_against_tree(
+        tree_data: &[u8],
+        leaf: [u8; 32],
+        proof: &[[u8; 32]],
+        root: [u8; 32],
+        leaf_index: u32,
+    ) -> Result<ProofStatus, IntegrityVerificationError> {
+        let tree = TreeAccount::parse(tree_data)?;
+        match tree.proof_length(proof.len()) {
+            ProofLength::Truncated => return Ok(ProofStatus::TruncatedProof),
+            ProofLength::Overlong => return Ok(ProofStatus::OverlongProof),
+            ProofLength::Full | ProofLength::CanopyTrimmed | ProofLength::PartiallyTrimmed => {}
+        }
+        // Only the current root is built from the nodes in the canopy
+        if tree.roots().first() == Some(&root) && !tree.canopy_agrees(proof, leaf_index)? {
+            return Ok(ProofStatus::CanopyMismatch);
+        }
+        if tree.compute_root(leaf, proof, leaf_index)? != Some(root) {
+            return Ok(ProofStatus::Inconsistent);
+        }
+        Ok(match tree.find_root(&root) {
+            RootMatch::Current => ProofStatus::ValidCurrent,
+            RootMatch::Recent { age } => {
+                debug!("Proof of leaf {} is {} changes old", leaf_index, age);
+                ProofStatus::ValidRecent
+            }
+            RootMatch::None => ProofStatus::Stale,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProofStatus, ProofValidator};
+    use crate::account_provider::InMemoryAccountProvider;
+    use crate::compressed_leaf::{leaf_hash, test_compressed_asset};
+    use crate::merkle_tree::test_tree_account;
+    use serde_json::{json, Value};
+    use solana_program::pubkey::Pubkey;
+    use spl_concurrent_merkle_tree::hash::recompute;
+    use spl_concurrent_merkle_tree::node::empty_node;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_check_proof_offline() {
+        let (first_asset, second_asset) = (test_compressed_asset(0), test_compressed_asset(1));
+        let first = leaf_hash(&first_asset["result"]).unwrap();
+        let second = leaf_hash(&second_asset["result"]).unwrap();
+        let tree_id = Pubkey::new_unique();
+        let mut accounts = InMemoryAccountProvider::default();
+        accounts.insert(tree_id, test_tree_account::<3, 8>(&[first, second]));
+        let validator =
+            ProofValidator::with_account_provider(Box::new(accounts), Duration::from_secs(60));
+
+        let response = |leaf: [u8; 32], proof: &[[u8; 32]], root: [u8; 32]| {
+            let node = |node: &[u8; 32]| Pubkey::new_from_array(*node).to_string();
+            json!({"result": {
+                "tree_id": tree_id.to_string(),
+                "leaf": node(&leaf),
+                "root": node(&root),
+                "proof": proof.iter().map(node).collect::<Vec<_>>(),
+            }})
+        };
+        let validator = &validator;
+        let check = |leaf, proof: [[u8; 32]; 3], root, asset: &Value| {
+            let response = response(leaf, &proof, root);
+            let asset = asset.clone();
+            async move { validator.check_proof(&response, &asset).await.unwrap() }
+        };
+
+        let current = [second, empty_node(1), empty_node(2)];
+        let current_root = recompute(first, &current, 0);
+        assert_eq!(
+            ProofStatus::ValidCurrent,
+            check(first, current, current_root, &first_asset).await
+        );
+        let recent = [empty_node(0), empty_node(1), empty_node(2)];
+        let recent_root = recompute(first, &recent, 0);
+        assert_eq!(
+            ProofStatus::ValidRecent,
+            check(first, recent, recent_root, &first_asset).await
+        );
+        let stale = [first, empty_node(1), empty_node(2)];
+        let stale_root = recompute(first, &stale, 0);
+        assert_eq!(
+            ProofStatus::Stale,
+            check(first, stale, stale_root, &first_asset).await
+        );
+        assert_eq!(
+            ProofStatus::Inconsistent,
+            check(first, current, recent_root, &first_asset).await
+        );
+        let proof_of_second = [first, empty_node(1), empty_node(2)];
+        assert_eq!(
+            ProofStatus::ValidCurrent,
+            check(second, proof_of_second, current_root, &second_asset).await
+        );
+        assert_eq!(
+            ProofStatus::LeafMismatch,
+            check(first, current, current_root, &second_asset).await
+        );
+
+        // The tree has no canopy to complete a shorter proof
+        let truncated = response(first, &current[..2], current_root);
+        assert_eq!(
+            ProofStatus::TruncatedProof,
+            validator
+                .check_proof(&truncated, &first_asset)
+                .await
+                .unwrap()
+        );
+        let overlong = response(
+            first,
+            &[second, empty_node(1), empty_node(2), empty_node(3)],
+            current_root,
+        );
+        assert_eq!(
+            ProofStatus::OverlongProof,
+            validator
+                .check_proof(&overlong, &first_asset)
+                .await
+                .unwrap()
+        );
+
+        // A node that cannot be read is not dropped from the proof
+        let mut malformed = response(first, &current, current_root);
+        malformed["result"]["proof"][1] = "not a node".into();
+        assert_eq!(
+            ProofStatus::MalformedProof,
+            validator
+                .check_proof(&malformed, &first_asset)
+                .await
+                .unwrap()
+        );
+
+        let mut unknown_tree = response(first, &current, current_root);
+        unknown_tree["result"]["tree_id"] = Pubkey::new_unique().to_string().into();
+        assert!(validator
+            .check_proof(&unknown_tree, &first_asset)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,372 @@
+use std::sync::Arc;
+
+use crate::{
+    api::{IntegrityVerificationApi, RequestTimings},
+    config::IntegrityVerificationConfig,
+    error::IntegrityVerificationError,
+    performance_measurement::{sample_gauges, Stats},
+    requests::Body,
+    time_series::LoadGauges,
+    timing_breakdown::TimingBreakdown,
+};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader},
+    sync::{mpsc, Semaphore},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Line of a captured traffic corpus
+#[derive(Deserialize, Debug)]
+pub struct CapturedRequest {
+    // Unix time the request was received at, in milliseconds
+    pub timestamp_ms: u64,
+    // JSON-RPC request as it was received
+    pub request: Value,
+}
+
+impl CapturedRequest {
+    /// Only single requests with a method can be replayed, batches are
+    /// rejected instead of being sent without a method
+    fn method(&self) -> Result<&str, String> {
+        if self.request.is_array() {
+            return Err("batch requests cannot be replayed".to_string());
+        }
+        self.request["method"]
+            .as_str()
+            .ok_or_else(|| "request without a method".to_string())
+    }
+}
+
+/// Reads a JSONL corpus and returns its requests ordered by time
+pub async fn load_corpus(path: &str) -> Result<Vec<CapturedRequest>, IntegrityVerificationError> {
+    let file = File::open(path).await?;
+    let mut lines = BufReader::new(file).lines();
+
+    let mut corpus = Vec::new();
+    let mut line_number = 0;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let captured = serde_json::from_str::<CapturedRequest>(&line)
+            .map_err(|e| e.to_string())
+            .and_then(|captured| {
+                captured.method()?;
+                Ok(captured)
+            })
+            .map_err(|e| {
+                IntegrityVerificationError::Corpus(format!("{} line {}: {}", path, line_number, e))
+            })?;
+        corpus.push(captured);
+    }
+    corpus.sort_by_key(|captured| captured.timestamp_ms);
+
+    Ok(corpus)
+}
+
+/// Returns when every request has to be sent, relative to the first one and
+/// sped up `speed` times
+pub fn schedule(corpus: &[CapturedRequest], speed: f64) -> Vec<Duration> {
+    let first = corpus.first().map(|captured| captured.timestamp_ms);
+    corpus
+        .iter()
+        .map(|captured| {
+            let offset = captured.timestamp_ms - first.unwrap_or_default();
+            Duration::from_secs_f64(offset as f64 / 1000.0 / speed)
+        })
+        .collect()
+}
+
+struct ReplayOutcome {
+    method: String,
+    response_time: u64,
+    // Failed requests carry the status code if there was a response
+    result: Result<RequestTimings, Option<u16>>,
+}
+
+impl ReplayOutcome {
+    fn record(self, stat: &mut Stats) {
+        match self.result {
+            Ok(timings) => {
+                stat.record_timings(&self.method, &timings);
+                stat.record_success(&self.method, self.response_time);
+            }
+            Err(code) => stat.record_failure(&self.method, code),
+        }
+    }
+}
+
+/// Replays the corpus of `config` against `testing_host`, keeping the time
+/// between requests, so bursts of the captured traffic are reproduced too
+pub async fn run_replay(
+    config: &IntegrityVerificationConfig,
+    cancel_token: CancellationToken,
+) -> Result<(), IntegrityVerificationError> {
+    let path = config.replay_corpus_path.as_deref().ok_or_else(|| {
+        IntegrityVerificationError::ValidateConfig("replay_corpus_path".to_string())
+    })?;
+    let corpus = load_corpus(path).await?;
+    info!(
+        "Replaying {} requests at {}x speed",
+        corpus.len(),
+        config.replay_speed
+    );
+
+    let (stat, run) = replay(config, corpus, cancel_token).await;
+
+    println!(
+        "Replayed {} requests in {} s, max dispatch lag {} ms",
+        run.sent,
+        run.duration.as_secs(),
+        run.max_lag.as_millis()
+    );
+    println!("{}", stat);
+    println!(
+        "{}",
+        TimingBreakdown {
+            stats: &stat,
+            duration: run.duration.as_secs(),
+        }
+    );
+
+    stat.time_series().export(
+        config.time_series_csv_path.as_deref(),
+        config.time_series_json_path.as_deref(),
+    )?;
+
+    Ok(())
+}
+
+/// How a replay went besides the stats of its requests
+struct ReplayRun {
+    sent: usize,
+    duration: Duration,
+    // Longest delay of a request past its scheduled time
+    max_lag: Duration,
+}
+
+async fn replay(
+    config: &IntegrityVerificationConfig,
+    corpus: Vec<CapturedRequest>,
+    cancel_token: CancellationToken,
+) -> (Stats, ReplayRun) {
+    let offsets = schedule(&corpus, config.replay_speed);
+    let api = Arc::new(IntegrityVerificationApi::new());
+    let in_flight = Arc::new(Semaphore::new(config.replay_max_in_flight));
+    let started = Instant::now();
+    let gauges = Arc::new(LoadGauges::default());
+    let sampler_token = CancellationToken::new();
+    let sampler = tokio::spawn(sample_gauges(
+        started,
+        gauges.clone(),
+        sampler_token.clone(),
+    ));
+
+    // Outcomes are recorded as they come, the collector owns the stats
+    let (outcomes_tx, mut outcomes_rx) = mpsc::unbounded_channel::<ReplayOutcome>();
+    let collector_token = CancellationToken::new();
+    let collector = tokio::spawn({
+        let collector_token = collector_token.clone();
+        async move {
+            let mut stat = Stats::starting_at(started);
+            loop {
+                tokio::select! {
+                    outcome = outcomes_rx.recv() => match outcome {
+                        Some(outcome) => outcome.record(&mut stat),
+                        None => break,
+                    },
+                    _ = collector_token.cancelled() => {
+                        outcomes_rx.close();
+                        while let Some(outcome) = outcomes_rx.recv().await {
+                            outcome.record(&mut stat);
+                        }
+                        break;
+                    }
+                }
+            }
+            stat
+        }
+    });
+
+    let mut sent = 0;
+    let mut max_lag = Duration::ZERO;
+    let mut interrupted_after = None;
+    for (captured, offset) in corpus.into_iter().zip(offsets) {
+        let send_at = started + offset;
+        let permit = tokio::select! {
+            permit = async {
+                tokio::time::sleep_until(send_at).await;
+                in_flight.clone().acquire_owned().await
+            } => permit.expect("semaphore is never closed"),
+            _ = cancel_token.cancelled() => {
+                warn!("Replay was interrupted");
+                interrupted_after = Some(started.elapsed());
+                break;
+            }
+        };
+        // Requests are late when the in-flight limit is reached
+        max_lag = max_lag.max(Instant::now().saturating_duration_since(send_at));
+
+        // Checked when the corpus was loaded
+        let method = captured.method().unwrap_or_default().to_string();
+        let body = Body::new(&method, captured.request["params"].clone());
+        let api = api.clone();
+        let url = config.testing_host.clone();
+        let gauges = gauges.clone();
+        let outcomes_tx = outcomes_tx.clone();
+        tokio::spawn(async move {
+            let start = Instant::now();
+            gauges.inc_in_flight();
+            let result = api.make_timed_request(&url, &json!(body).to_string()).await;
+            gauges.dec_in_flight();
+
+            let result = match result {
+                Ok((_, timings)) => Ok(timings),
+                Err(IntegrityVerificationError::ResponseStatusCode(code)) => Err(Some(code)),
+                Err(_) => Err(None),
+            };
+            let _ = outcomes_tx.send(ReplayOutcome {
+                method,
+                response_time: start.elapsed().as_millis() as u64,
+                result,
+            });
+            drop(permit);
+        });
+        sent += 1;
+    }
+    let replay_duration = interrupted_after.unwrap_or_else(|| started.elapsed());
+
+    // All permits are back once the last response arrived
+    let stop_timeout = Duration::from_secs(config.stop_timeout);
+    let max_in_flight = config.replay_max_in_flight as u32;
+    if tokio::time::timeout(stop_timeout, in_flight.acquire_many(max_in_flight))
+        .await
+        .is_err()
+    {
+        warn!(
+            "In-flight requests did not finish within {} s, dropping them",
+            stop_timeout.as_secs()
+        );
+    }
+    drop(outcomes_tx);
+    collector_token.cancel();
+    let mut stat = collector.await.expect("collector does not panic");
+    if let Some(after) = interrupted_after {
+        stat.mark_interrupted(after);
+    }
+
+    sampler_token.cancel();
+    if let Ok(gauges_series) = sampler.await {
+        stat.merge_time_series(&gauges_series);
+    }
+
+    (
+        stat,
+        ReplayRun {
+            sent,
+            duration: replay_duration,
+            max_lag,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_corpus, replay, schedule, CapturedRequest};
+    use crate::{api::test_json_rpc_server, config::IntegrityVerificationConfig};
+    use serde_json::{json, Value};
+    use std::{io::Write, sync::atomic::Ordering, time::Duration};
+    use tokio_util::sync::CancellationToken;
+
+    #[test]
+    fn test_schedule() {
+        let corpus: Vec<CapturedRequest> = [1_000, 1_500, 3_000]
+            .into_iter()
+            .map(|timestamp_ms| CapturedRequest {
+                timestamp_ms,
+                request: Value::Null,
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(250),
+                Duration::from_secs(1)
+            ],
+            schedule(&corpus, 2.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_corpus() {
+        let mut corpus = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            corpus,
+            r#"{{"timestamp_ms": 2, "request": {{"method": "getAsset", "params": {{}}}}}}"#
+        )
+        .unwrap();
+        writeln!(
+            corpus,
+            r#"{{"timestamp_ms": 1, "request": {{"method": "getAssetProof", "params": {{}}}}}}"#
+        )
+        .unwrap();
+        let path = corpus.path().to_str().unwrap().to_string();
+        let loaded = load_corpus(&path).await.unwrap();
+        assert_eq!(
+            vec![1, 2],
+            loaded.iter().map(|c| c.timestamp_ms).collect::<Vec<_>>()
+        );
+
+        writeln!(corpus).unwrap();
+        writeln!(
+            corpus,
+            r#"{{"timestamp_ms": 3, "request": [{{"method": "getAsset", "params": {{}}}}]}}"#
+        )
+        .unwrap();
+        let err = load_corpus(&path).await.unwrap_err().to_string();
+        assert!(err.contains("line 4: batch requests"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replay() {
+        let (url, requests) = test_json_rpc_server(
+            Duration::from_millis(100),
+            |request| json!({"jsonrpc": "2.0", "id": request["id"], "result": {}}),
+        )
+        .await;
+        let config: IntegrityVerificationConfig = serde_json::from_value(json!({
+            "reference_host": url,
+            "testing_host": url,
+            "testing_file_path": "",
+            "num_of_virtual_users": 1,
+            "test_duration_time": 1,
+            "replay_max_in_flight": 1,
+        }))
+        .unwrap();
+        // Captured at the same time, each one waits for the one before
+        let corpus = (0..4)
+            .map(|_| CapturedRequest {
+                timestamp_ms: 1_000,
+                request: json!({"jsonrpc": "2.0", "id": 1, "method": "getAsset", "params": {"id": "asset"}}),
+            })
+            .collect();
+
+        let (stat, run) = replay(&config, corpus, CancellationToken::new()).await;
+        assert_eq!(4, run.sent);
+        assert_eq!(4, requests.load(Ordering::Relaxed));
+        assert_eq!(4, stat.overall().total_requests());
+        assert_eq!(4, stat.methods()["getAsset"].total_requests());
+        assert!(
+            run.max_lag >= Duration::from_millis(250),
+            "{:?}",
+            run.max_lag
+        );
+    }
+}
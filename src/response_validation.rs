@@ -0,0 +1,243 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use crate::{
+    account_provider::AccountFetches,
+    config::IntegrityVerificationConfig,
+    diff_checker::GET_ASSET_PROOF_METHOD,
+    proof_validator::{ProofStatus, ProofValidator},
+    requests::Body,
+};
+use rand::Rng;
+use serde::{Deserialize as _, Deserializer, Serializer};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ValidationFailure {
+    MalformedJsonRpc,
+    ErrorPayload,
+    MissingResult,
+    TooManyItems,
+    // Proof checked against the tree with a status other than valid
+    Proof(ProofStatus),
+}
+
+impl fmt::Display for ValidationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failure = match self {
+            ValidationFailure::MalformedJsonRpc => "malformed JSON-RPC",
+            ValidationFailure::ErrorPayload => "error payload",
+            ValidationFailure::MissingResult => "missing result",
+            ValidationFailure::TooManyItems => "more items than requested",
+            ValidationFailure::Proof(status) => return write!(f, "proof: {}", status),
+        };
+        write!(f, "{}", failure)
+    }
+}
+
+/// Serializes failure counts as a list of pairs, JSON map keys cannot hold
+/// the proof status of a failure
+pub fn serialize_failure_counts<S: Serializer>(
+    counts: &HashMap<ValidationFailure, u64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(counts.iter())
+}
+
+pub fn deserialize_failure_counts<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<ValidationFailure, u64>, D::Error> {
+    Ok(Vec::<(ValidationFailure, u64)>::deserialize(deserializer)?
+        .into_iter()
+        .collect())
+}
+
+/// Proof check of a response that passed validation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofCheck {
+    // Not a proof response, or proofs are not validated
+    Skipped,
+    Valid(ProofStatus),
+    // The account provider or the `getAsset` request the check needs failed,
+    // which says nothing about the response
+    Error,
+}
+
+/// Checks the shape of a response to `body` without comparing it to anything
+pub fn validate_response(body: &Body, response: &Value) -> Result<(), ValidationFailure> {
+    if response["jsonrpc"] != "2.0" || response.get("id").is_none() {
+        return Err(ValidationFailure::MalformedJsonRpc);
+    }
+    if response.get("error").is_some() {
+        return Err(ValidationFailure::ErrorPayload);
+    }
+    let result = &response["result"];
+    if result.is_null() {
+        return Err(ValidationFailure::MissingResult);
+    }
+
+    if let Some(limit) = body.params["limit"].as_u64() {
+        let items = result["items"]
+            .as_array()
+            .or_else(|| result["token_accounts"].as_array());
+        if items.is_some_and(|items| items.len() as u64 > limit) {
+            return Err(ValidationFailure::TooManyItems);
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates a sample of the responses received during a load test
+pub struct ResponseValidator {
+    sample_rate: f64,
+    proof_validator: Option<ProofValidator>,
+}
+
+impl ResponseValidator {
+    pub fn from_config(config: &IntegrityVerificationConfig) -> Option<Self> {
+        if config.validation_sample_rate <= 0.0 {
+            return None;
+        }
+
+        Some(Self {
+            sample_rate: config.validation_sample_rate,
+            proof_validator: config.validate_proofs.then(|| ProofValidator::new(config)),
+        })
+    }
+
+    /// Returns the tree account fetches sent to every RPC node so far
+    pub fn account_fetches(&self) -> BTreeMap<String, AccountFetches> {
+        self.proof_validator
+            .as_ref()
+            .map(ProofValidator::account_fetches)
+            .unwrap_or_default()
+    }
+
+    /// Samples the responses to validate with `rnd`, the seeded generator of
+    /// the worker, so the same responses are validated with the same seed
+    pub fn should_validate(&self, rnd: &mut impl Rng) -> bool {
+        rnd.gen_bool(self.sample_rate)
+    }
+
+    /// Returns how the proof of a valid response was checked
+    pub async fn validate(
+        &self,
+        host: &str,
+        body: &Body,
+        response: &Value,
+    ) -> Result<ProofCheck, ValidationFailure> {
+        validate_response(body, response)?;
+
+        if let (Some(proof_validator), GET_ASSET_PROOF_METHOD) =
+            (&self.proof_validator, body.method.as_str())
+        {
+            let asset_id = body.params["id"].as_str().unwrap_or_default();
+            match proof_validator
+                .check_proof_valid(host, asset_id, response)
+                .await
+            {
+                Ok(status) if status.is_valid() => return Ok(ProofCheck::Valid(status)),
+                Ok(status) => return Err(ValidationFailure::Proof(status)),
+                Err(err) => {
+                    warn!("Cannot check the proof of {}: {}", asset_id, err);
+                    return Ok(ProofCheck::Error);
+                }
+            }
+        }
+
+        Ok(ProofCheck::Skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        deserialize_failure_counts, serialize_failure_counts, validate_response, ResponseValidator,
+        ValidationFailure,
+    };
+    use crate::{proof_validator::ProofStatus, requests::Body};
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_derive::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_validate_response() {
+        let body = Body::new(
+            "getAssetsByOwner",
+            json!({"ownerAddress": "key", "limit": 2}),
+        );
+
+        assert_eq!(
+            Ok(()),
+            validate_response(
+                &body,
+                &json!({"jsonrpc": "2.0", "id": 0, "result": {"items": [{}, {}]}})
+            )
+        );
+        assert_eq!(
+            Err(ValidationFailure::MalformedJsonRpc),
+            validate_response(&body, &json!({"result": {"items": []}}))
+        );
+        assert_eq!(
+            Err(ValidationFailure::ErrorPayload),
+            validate_response(
+                &body,
+                &json!({"jsonrpc": "2.0", "id": 0, "error": {"code": -32000}})
+            )
+        );
+        assert_eq!(
+            Err(ValidationFailure::MissingResult),
+            validate_response(&body, &json!({"jsonrpc": "2.0", "id": 0}))
+        );
+        assert_eq!(
+            Err(ValidationFailure::TooManyItems),
+            validate_response(
+                &body,
+                &json!({"jsonrpc": "2.0", "id": 0, "result": {"items": [{}, {}, {}]}})
+            )
+        );
+    }
+
+    #[test]
+    fn test_failure_counts() {
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Counts {
+            #[serde(
+                serialize_with = "serialize_failure_counts",
+                deserialize_with = "deserialize_failure_counts"
+            )]
+            failures: HashMap<ValidationFailure, u64>,
+        }
+
+        let stale = ValidationFailure::Proof(ProofStatus::Stale);
+        assert_eq!("proof: stale", stale.to_string());
+        let counts = Counts {
+            failures: HashMap::from([(stale, 2), (ValidationFailure::ErrorPayload, 1)]),
+        };
+        let json = serde_json::to_string(&counts).unwrap();
+        assert_eq!(counts, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn test_sampling_follows_seed() {
+        let validator = ResponseValidator {
+            sample_rate: 0.3,
+            proof_validator: None,
+        };
+        let samples = |seed: u64| {
+            let mut rnd = StdRng::seed_from_u64(seed);
+            (0..100)
+                .map(|_| validator.should_validate(&mut rnd))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(samples(5), samples(5));
+        assert_ne!(samples(5), samples(6));
+    }
+}
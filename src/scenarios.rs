@@ -0,0 +1,303 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use crate::{
+    diff_checker::LOAD_METHODS,
+    file_keys_fetcher::FileKeysFetcher,
+    params_generation::ParamsDistribution,
+    performance_measurement::{build_body, LoadRequest},
+};
+use rand::{distributions::WeightedIndex, rngs::StdRng, Rng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+// Mixed into the seed of a worker, so the choices of its journeys do not
+// follow the ones its keys fetcher makes with the same seed
+const JOURNEYS_SEED_MASK: u64 = 0x9e37_79b9_7f4a_7c15;
+
+const fn default_scenario_weight() -> u32 {
+    1
+}
+
+/// Sequence of requests a single user makes, like a wallet listing the assets
+/// of its owner and then opening one of them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    // Share of the journeys following this scenario
+    #[serde(default = "default_scenario_weight")]
+    pub weight: u32,
+    pub steps: Vec<ScenarioStep>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScenarioStep {
+    // Keys file method of the request
+    pub method: String,
+    // Key taken from the response of an earlier step instead of the keys file
+    #[serde(default)]
+    pub key_from: Option<KeySource>,
+    // Pause before the request
+    #[serde(default)]
+    pub think_time_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeySource {
+    // Index of the step whose response holds the key
+    pub step: usize,
+    // Path of the key in the response, like `result.items[rand].id`
+    pub path: String,
+}
+
+impl Scenario {
+    /// Whether a later step takes its key from the response of step `index`
+    fn is_key_source(&self, index: usize) -> bool {
+        self.steps.iter().any(|step| {
+            step.key_from
+                .as_ref()
+                .is_some_and(|source| source.step == index)
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.steps.is_empty() {
+            return Err("no steps".to_string());
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            // Steps taking their keys from responses are not checked against
+            // the keys file, an unknown method would only fail mid-run
+            if !LOAD_METHODS.contains(&step.method.as_str()) {
+                return Err(format!("step {} has unknown method {}", index, step.method));
+            }
+            if let Some(source) = &step.key_from {
+                if source.step >= index {
+                    return Err(format!(
+                        "step {} takes its key from step {}, which is not an earlier step",
+                        index, source.step
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Follows `path` in `response` and returns the string or number found there.
+/// Array elements are selected with `[<index>]` or picked at random with
+/// `[rand]`
+pub fn extract_key(response: &Value, path: &str, rnd: &mut impl Rng) -> Option<String> {
+    let mut value = response;
+    for segment in path.split('.') {
+        let (field, index) = match segment.split_once('[') {
+            Some((field, index)) => (field, Some(index.strip_suffix(']')?)),
+            None => (segment, None),
+        };
+        if !field.is_empty() {
+            value = value.get(field)?;
+        }
+        if let Some(index) = index {
+            let items = value.as_array()?;
+            let index = match index {
+                "rand" if !items.is_empty() => rnd.gen_range(0..items.len()),
+                _ => index.parse().ok()?,
+            };
+            value = items.get(index)?;
+        }
+    }
+
+    match value {
+        Value::String(key) => Some(key.clone()),
+        Value::Number(key) => Some(key.to_string()),
+        _ => None,
+    }
+}
+
+/// Requests of a single worker walking through the scenarios one journey
+/// after another
+pub struct Journeys {
+    scenarios: Arc<Vec<Scenario>>,
+    choice: WeightedIndex<u32>,
+    keys_fetcher: FileKeysFetcher,
+    params_distributions: Arc<HashMap<String, ParamsDistribution>>,
+    rnd: StdRng,
+    scenario: usize,
+    step: usize,
+    // Responses of the steps of the current journey, `None` for failed ones
+    // and the ones no later step takes its key from
+    responses: Vec<Option<Value>>,
+}
+
+impl Journeys {
+    pub fn new(
+        scenarios: Arc<Vec<Scenario>>,
+        choice: WeightedIndex<u32>,
+        keys_fetcher: FileKeysFetcher,
+        params_distributions: Arc<HashMap<String, ParamsDistribution>>,
+        seed: u64,
+    ) -> Self {
+        Self {
+            scenarios,
+            choice,
+            keys_fetcher,
+            params_distributions,
+            rnd: StdRng::seed_from_u64(seed ^ JOURNEYS_SEED_MASK),
+            scenario: 0,
+            step: 0,
+            responses: Vec::new(),
+        }
+    }
+
+    /// Random generator of the keys fetcher of the worker
+    pub fn rnd(&mut self) -> &mut StdRng {
+        self.keys_fetcher.rnd()
+    }
+
+    /// Returns the request of the next step. A journey whose step cannot get
+    /// its key, because an earlier request failed or returned no items, is
+    /// abandoned and a new one starts
+    pub fn next_request(&mut self) -> LoadRequest {
+        loop {
+            if self.step == 0 {
+                self.scenario = self.rnd.sample(&self.choice);
+                self.responses.clear();
+            }
+            let scenario = &self.scenarios[self.scenario];
+            let step = &scenario.steps[self.step];
+
+            let key = match &step.key_from {
+                None => self.keys_fetcher.get_key(&step.method),
+                Some(source) => self
+                    .responses
+                    .get(source.step)
+                    .and_then(Option::as_ref)
+                    .and_then(|response| extract_key(response, &source.path, &mut self.rnd)),
+            };
+            let key = match key {
+                Some(key) => key,
+                None => {
+                    debug!(
+                        "Step {} of scenario {} has no key, starting a new journey",
+                        self.step, scenario.name
+                    );
+                    self.step = 0;
+                    continue;
+                }
+            };
+
+            let distribution = self
+                .params_distributions
+                .get(&step.method)
+                .cloned()
+                .unwrap_or_default();
+            return LoadRequest {
+                method: step.method.clone(),
+                key: key.clone(),
+                body: build_body(&step.method, key, &distribution, &mut self.rnd),
+                think_time: Duration::from_millis(step.think_time_ms),
+            };
+        }
+    }
+
+    /// Keeps the response of the current step if a step after it needs it
+    pub fn record_response(&mut self, response: Option<&Value>) {
+        let scenario = &self.scenarios[self.scenario];
+        self.responses.push(
+            response
+                .filter(|_| scenario.is_key_source(self.step))
+                .cloned(),
+        );
+        self.step += 1;
+        if self.step == self.scenarios[self.scenario].steps.len() {
+            self.step = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_key, Journeys, Scenario};
+    use crate::file_keys_fetcher::FileKeysFetcher;
+    use rand::{distributions::WeightedIndex, rngs::StdRng, SeedableRng};
+    use serde_json::json;
+    use std::{collections::HashMap, sync::Arc};
+
+    #[test]
+    fn test_extract_key() {
+        let mut rnd = StdRng::seed_from_u64(0);
+        let response = json!({"result": {"total": 2, "items": [{"id": "a"}, {"id": "b"}]}});
+
+        assert_eq!(
+            Some("b".to_string()),
+            extract_key(&response, "result.items[1].id", &mut rnd)
+        );
+        assert_eq!(
+            Some("2".to_string()),
+            extract_key(&response, "result.total", &mut rnd)
+        );
+        let random = extract_key(&response, "result.items[rand].id", &mut rnd).unwrap();
+        assert!(random == "a" || random == "b");
+        assert_eq!(None, extract_key(&response, "result.items[2].id", &mut rnd));
+        assert_eq!(
+            None,
+            extract_key(
+                &json!({"result": {"items": []}}),
+                "result.items[rand].id",
+                &mut rnd
+            )
+        );
+        assert_eq!(None, extract_key(&response, "result.items", &mut rnd));
+    }
+
+    #[test]
+    fn test_journeys_keep_needed_responses() {
+        let scenario: Scenario = serde_json::from_value(json!({
+            "name": "wallet",
+            "steps": [
+                {"method": "getAssetsByOwner"},
+                {"method": "getAsset", "key_from": {"step": 0, "path": "result.items[0].id"}},
+                {"method": "getAssetProof", "key_from": {"step": 0, "path": "result.items[0].id"}},
+            ],
+        }))
+        .unwrap();
+        let keys_fetcher = FileKeysFetcher::from_keys(HashMap::from([(
+            "getAssetsByOwner".to_string(),
+            vec!["owner".to_string()],
+        )]));
+        let mut journeys = Journeys::new(
+            Arc::new(vec![scenario]),
+            WeightedIndex::new([1]).unwrap(),
+            keys_fetcher,
+            Arc::new(HashMap::new()),
+            0,
+        );
+
+        let response = json!({"result": {"items": [{"id": "asset"}]}});
+        assert_eq!("owner", journeys.next_request().key);
+        journeys.record_response(Some(&response));
+        assert_eq!("asset", journeys.next_request().key);
+        journeys.record_response(Some(&response));
+        assert_eq!(vec![Some(response), None], journeys.responses);
+        assert_eq!("asset", journeys.next_request().key);
+    }
+
+    #[test]
+    fn test_validate_step_methods() {
+        let scenario = |method: &str| -> Scenario {
+            serde_json::from_value(json!({
+                "name": "wallet",
+                "steps": [
+                    {"method": "getAssetsByOwner"},
+                    {"method": method, "key_from": {"step": 0, "path": "result.items[rand].id"}},
+                ],
+            }))
+            .unwrap()
+        };
+
+        assert!(scenario("getAsset").validate().is_ok());
+        assert_eq!(
+            Err("step 1 has unknown method getAssets".to_string()),
+            scenario("getAssets").validate()
+        );
+    }
+}
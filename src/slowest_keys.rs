@@ -0,0 +1,185 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Latency and outcomes of every request sent for a single key of a method
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeyStats {
+    // Params of the slowest request
+    pub params: Value,
+    pub requests: u64,
+    pub total_millis: u64,
+    pub max_millis: u64,
+    // Counts of "ok", "status <code>", "invalid: <reason>" and request errors
+    pub outcomes: BTreeMap<String, u64>,
+}
+
+impl KeyStats {
+    pub fn mean_millis(&self) -> u64 {
+        self.total_millis
+            .checked_div(self.requests)
+            .unwrap_or_default()
+    }
+
+    fn merge(&mut self, other: KeyStats) {
+        self.requests += other.requests;
+        self.total_millis += other.total_millis;
+        if other.max_millis > self.max_millis {
+            self.max_millis = other.max_millis;
+            self.params = other.params;
+        }
+        for (outcome, number) in other.outcomes {
+            *self.outcomes.entry(outcome).or_insert(0) += number;
+        }
+    }
+}
+
+/// Requests of a performance run grouped by method and key
+#[derive(Serialize, Deserialize, Default)]
+pub struct KeyLatencies {
+    // Stats of every key of every method
+    methods: HashMap<String, HashMap<String, KeyStats>>,
+}
+
+impl KeyLatencies {
+    pub fn record(
+        &mut self,
+        method: &str,
+        key: &str,
+        params: &Value,
+        time: u64,
+        outcome: Cow<'static, str>,
+    ) {
+        let key_stats = self
+            .methods
+            .entry(method.to_string())
+            .or_default()
+            .entry(key.to_string())
+            .or_insert_with(|| KeyStats {
+                params: Value::Null,
+                requests: 0,
+                total_millis: 0,
+                max_millis: 0,
+                outcomes: BTreeMap::new(),
+            });
+        if key_stats.requests == 0 || time > key_stats.max_millis {
+            key_stats.max_millis = time;
+            key_stats.params = params.clone();
+        }
+        key_stats.requests += 1;
+        key_stats.total_millis += time;
+        match key_stats.outcomes.get_mut(outcome.as_ref()) {
+            Some(number) => *number += 1,
+            None => {
+                key_stats.outcomes.insert(outcome.into_owned(), 1);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: KeyLatencies) {
+        for (method, other_keys) in other.methods {
+            let keys = self.methods.entry(method).or_default();
+            for (key, other_stats) in other_keys {
+                match keys.get_mut(&key) {
+                    Some(key_stats) => key_stats.merge(other_stats),
+                    None => {
+                        keys.insert(key, other_stats);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the `n` keys with the highest max latency, the mean breaks ties
+    pub fn slowest(&self, n: usize) -> Vec<(&str, &str, &KeyStats)> {
+        let mut keys: Vec<_> = self
+            .methods
+            .iter()
+            .flat_map(|(method, keys)| {
+                keys.iter()
+                    .map(|(key, key_stats)| (method.as_str(), key.as_str(), key_stats))
+            })
+            .collect();
+        keys.sort_by(|(_, _, a), (_, _, b)| {
+            (b.max_millis, b.mean_millis()).cmp(&(a.max_millis, a.mean_millis()))
+        });
+        keys.truncate(n);
+        keys
+    }
+}
+
+pub struct SlowestKeysReport<'a> {
+    pub keys: Vec<(&'a str, &'a str, &'a KeyStats)>,
+}
+
+impl fmt::Display for SlowestKeysReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "---\nSlowest keys:\nmethod - key - max ms - mean ms - requests - outcomes - params"
+        )?;
+        for (method, key, key_stats) in self.keys.iter() {
+            let outcomes: Vec<String> = key_stats
+                .outcomes
+                .iter()
+                .map(|(outcome, number)| format!("{} x{}", outcome, number))
+                .collect();
+            write!(
+                f,
+                "\n{} - {} - {} - {} - {} - {} - {}",
+                method,
+                key,
+                key_stats.max_millis,
+                key_stats.mean_millis(),
+                key_stats.requests,
+                outcomes.join(", "),
+                key_stats.params
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyLatencies;
+    use serde_json::json;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_slowest_keys() {
+        let mut first = KeyLatencies::default();
+        first.record("getAsset", "fast", &json!({}), 10, Cow::Borrowed("ok"));
+        first.record("getAsset", "slow", &json!({"page": 1}), 100, "ok".into());
+        first.record("getAsset", "slow", &json!({"page": 2}), 300, "ok".into());
+        let mut second = KeyLatencies::default();
+        second.record(
+            "getAsset",
+            "slow",
+            &json!({"page": 3}),
+            200,
+            Cow::Owned("status 500".to_string()),
+        );
+        second.record("getAssetsByOwner", "whale", &json!({}), 900, "ok".into());
+        first.merge(second);
+
+        let slowest = first.slowest(2);
+        assert_eq!(2, slowest.len());
+        assert_eq!(("getAssetsByOwner", "whale"), (slowest[0].0, slowest[0].1));
+        assert_eq!(("getAsset", "slow"), (slowest[1].0, slowest[1].1));
+        assert_eq!(3, slowest[1].2.requests);
+        assert_eq!(200, slowest[1].2.mean_millis());
+        // Params of the slowest request are kept
+        assert_eq!(json!({"page": 2}), slowest[1].2.params);
+        assert_eq!(
+            vec!["ok", "status 500"],
+            slowest[1].2.outcomes.keys().collect::<Vec<_>>()
+        );
+    }
+}
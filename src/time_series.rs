@@ -0,0 +1,172 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{error::IntegrityVerificationError, histogram::Histogram};
+use serde_derive::{Deserialize, Serialize};
+
+/// Load generator state sampled once per second
+#[derive(Default)]
+pub struct LoadGauges {
+    in_flight: AtomicU64,
+    active_workers: AtomicU64,
+}
+
+impl LoadGauges {
+    pub fn inc_in_flight(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec_in_flight(&self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_active_workers(&self) {
+        self.active_workers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec_active_workers(&self) {
+        self.active_workers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Bucket {
+    requests: u64,
+    errors: u64,
+    response_times: Histogram,
+    in_flight: u64,
+    active_workers: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TimeSeriesRow {
+    second: usize,
+    requests: u64,
+    errors: u64,
+    p50_ms: Option<u64>,
+    p95_ms: Option<u64>,
+    p99_ms: Option<u64>,
+    in_flight: u64,
+    active_workers: u64,
+}
+
+/// Per-second buckets of a performance run
+#[derive(Serialize, Deserialize, Default)]
+pub struct TimeSeries {
+    buckets: Vec<Bucket>,
+}
+
+impl TimeSeries {
+    fn bucket(&mut self, second: usize) -> &mut Bucket {
+        if self.buckets.len() <= second {
+            self.buckets.resize_with(second + 1, Bucket::default);
+        }
+        &mut self.buckets[second]
+    }
+
+    /// Records a finished request, `response_time` is `None` for failed ones
+    pub fn record(&mut self, second: usize, response_time: Option<u64>) {
+        let bucket = self.bucket(second);
+        bucket.requests += 1;
+        match response_time {
+            Some(time) => bucket.response_times.record(time),
+            None => bucket.errors += 1,
+        }
+    }
+
+    pub fn sample_gauges(&mut self, second: usize, gauges: &LoadGauges) {
+        let bucket = self.bucket(second);
+        bucket.in_flight = gauges.in_flight.load(Ordering::Relaxed);
+        bucket.active_workers = gauges.active_workers.load(Ordering::Relaxed);
+    }
+
+    /// Adds up the requests and gauges of both series, gauges sampled by
+    /// different processes add up to the gauges of the whole load
+    pub fn merge(&mut self, other: &TimeSeries) {
+        for (second, other_bucket) in other.buckets.iter().enumerate() {
+            let bucket = self.bucket(second);
+            bucket.requests += other_bucket.requests;
+            bucket.errors += other_bucket.errors;
+            bucket.response_times.merge(&other_bucket.response_times);
+            bucket.in_flight += other_bucket.in_flight;
+            bucket.active_workers += other_bucket.active_workers;
+        }
+    }
+
+    pub fn rows(&self) -> Vec<TimeSeriesRow> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(second, bucket)| TimeSeriesRow {
+                second,
+                requests: bucket.requests,
+                errors: bucket.errors,
+                p50_ms: bucket.response_times.percentile(50.0),
+                p95_ms: bucket.response_times.percentile(95.0),
+                p99_ms: bucket.response_times.percentile(99.0),
+                in_flight: bucket.in_flight,
+                active_workers: bucket.active_workers,
+            })
+            .collect()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            "second,requests,errors,p50_ms,p95_ms,p99_ms,in_flight,active_workers\n".to_string();
+        let optional = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+        for row in self.rows() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                row.second,
+                row.requests,
+                row.errors,
+                optional(row.p50_ms),
+                optional(row.p95_ms),
+                optional(row.p99_ms),
+                row.in_flight,
+                row.active_workers
+            ));
+        }
+        csv
+    }
+
+    pub fn export(
+        &self,
+        csv_path: Option<&str>,
+        json_path: Option<&str>,
+    ) -> Result<(), IntegrityVerificationError> {
+        if let Some(path) = csv_path {
+            std::fs::write(path, self.to_csv())?;
+        }
+        if let Some(path) = json_path {
+            std::fs::write(path, serde_json::to_string_pretty(&self.rows())?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoadGauges, TimeSeries};
+
+    #[test]
+    fn test_time_series_csv() {
+        let gauges = LoadGauges::default();
+        gauges.inc_active_workers();
+        gauges.inc_in_flight();
+
+        let mut time_series = TimeSeries::default();
+        time_series.record(0, Some(10));
+        time_series.record(0, Some(30));
+        time_series.record(0, None);
+        time_series.sample_gauges(0, &gauges);
+        time_series.record(2, Some(5));
+
+        assert_eq!(
+            "second,requests,errors,p50_ms,p95_ms,p99_ms,in_flight,active_workers\n\
+             0,3,1,10,30,30,1,1\n\
+             1,0,0,,,,0,0\n\
+             2,1,0,5,5,5,0,0\n",
+            time_series.to_csv()
+        );
+    }
+}
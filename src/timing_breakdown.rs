@@ -0,0 +1,109 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    api::RequestTimings,
+    histogram::Histogram,
+    performance_measurement::{RequestStats, Stats},
+};
+use serde_derive::{Deserialize, Serialize};
+
+/// Phases of successfully received responses, in microseconds
+#[derive(Serialize, Deserialize, Default)]
+pub struct PhaseTimings {
+    // Time to first byte over pooled connections, the server and network time
+    time_to_first_byte: Histogram,
+    // Time to first byte of requests that connected first
+    #[serde(default)]
+    new_connection_time_to_first_byte: Histogram,
+    body_transfer: Histogram,
+    parse: Histogram,
+    response_bytes: u64,
+}
+
+impl PhaseTimings {
+    pub fn record(&mut self, timings: &RequestTimings) {
+        let time_to_first_byte = if timings.new_connection {
+            &mut self.new_connection_time_to_first_byte
+        } else {
+            &mut self.time_to_first_byte
+        };
+        time_to_first_byte.record(timings.time_to_first_byte.as_micros() as u64);
+        self.body_transfer
+            .record(timings.body_transfer.as_micros() as u64);
+        self.parse.record(timings.parse.as_micros() as u64);
+        self.response_bytes += timings.response_bytes;
+    }
+
+    pub fn merge(&mut self, other: &PhaseTimings) {
+        self.time_to_first_byte.merge(&other.time_to_first_byte);
+        self.new_connection_time_to_first_byte
+            .merge(&other.new_connection_time_to_first_byte);
+        self.body_transfer.merge(&other.body_transfer);
+        self.parse.merge(&other.parse);
+        self.response_bytes += other.response_bytes;
+    }
+
+    pub fn response_bytes(&self) -> u64 {
+        self.response_bytes
+    }
+
+    fn average_response_size(&self) -> u64 {
+        self.response_bytes
+            .checked_div(
+                self.time_to_first_byte.count() + self.new_connection_time_to_first_byte.count(),
+            )
+            .unwrap_or_default()
+    }
+}
+
+/// Per method report of where the response time goes
+pub struct TimingBreakdown<'a> {
+    pub stats: &'a Stats,
+    pub duration: u64,
+}
+
+impl TimingBreakdown<'_> {
+    fn write_row(&self, f: &mut fmt::Formatter, scope: &str, stats: &RequestStats) -> fmt::Result {
+        let phases = stats.phases();
+        let millis = |histogram: &Histogram, percentile: f64| {
+            histogram.percentile(percentile).unwrap_or_default() as f64 / 1000.0
+        };
+        let phase = |histogram: &Histogram| {
+            format!(
+                "{:.2} / {:.2}",
+                millis(histogram, 50.0),
+                millis(histogram, 95.0)
+            )
+        };
+        write!(
+            f,
+            "\n{} - {} - {} ({}) - {} - {} - {} - {:.0}",
+            scope,
+            phase(&phases.time_to_first_byte),
+            phase(&phases.new_connection_time_to_first_byte),
+            phases.new_connection_time_to_first_byte.count(),
+            phase(&phases.body_transfer),
+            phase(&phases.parse),
+            phases.average_response_size(),
+            phases.response_bytes as f64 / self.duration.max(1) as f64
+        )
+    }
+}
+
+impl fmt::Display for TimingBreakdown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "---\nTiming breakdown, p50 / p95 ms:\n\
+             method - time to first byte - time to first byte on new connections (number) - \
+             body transfer - parse - avg response bytes - bytes/sec"
+        )?;
+        self.write_row(f, "overall", self.stats.overall())?;
+        let methods: BTreeMap<_, _> = self.stats.methods().iter().collect();
+        for (method, method_stats) in methods {
+            self.write_row(f, method, method_stats)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    account_provider::AccountFetches, error::IntegrityVerificationError,
+    interfaces::AccountProvider,
+};
+use solana_program::pubkey::Pubkey;
+use tracing::debug;
+
+/// Tree account as read at `slot`
+#[derive(Debug, PartialEq)]
+pub struct CachedTree {
+    pub data: Vec<u8>,
+    pub slot: u64,
+    pub fetched_at: Instant,
+}
+
+// Latest read of a tree, locked while the tree is fetched so concurrent
+// validations wait for that fetch instead of starting their own
+type TreeEntry = Arc<tokio::sync::Mutex<Option<Arc<CachedTree>>>>;
+
+/// Tree accounts shared by proof validations, so proofs of the same tree are
+/// checked against a single read of it until one of them fails against it or
+/// the read gets older than `max_age`
+pub struct TreeCache {
+    account_provider: Box<dyn AccountProvider + Send + Sync>,
+    max_age: Duration,
+    trees: Mutex<HashMap<Pubkey, TreeEntry>>,
+}
+
+impl TreeCache {
+    pub fn new(
+        account_provider: Box<dyn AccountProvider + Send + Sync>,
+        max_age: Duration,
+    ) -> Self {
+        Self {
+            account_provider,
+            max_age,
+            trees: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn account_fetches(&self) -> BTreeMap<String, AccountFetches> {
+        self.account_provider.account_fetches()
+    }
+
+    fn entry(&self, tree: &Pubkey) -> TreeEntry {
+        self.trees.lock().unwrap().entry(*tree).or_default().clone()
+    }
+
+    async fn fetch(
+        &self,
+        tree: &Pubkey,
+        cached: &mut Option<Arc<CachedTree>>,
+    ) -> Result<Arc<CachedTree>, IntegrityVerificationError> {
+        let min_slot = cached.as_ref().map(|cached| cached.slot);
+        let (data, slot) = self
+            .account_provider
+            .get_account_at_slot(tree, min_slot)
+            .await?
+            .ok_or(IntegrityVerificationError::NullAssetAccount(
+                tree.to_string(),
+            ))?;
+        let fetched = Arc::new(CachedTree {
+            data,
+            slot,
+            fetched_at: Instant::now(),
+        });
+        *cached = Some(fetched.clone());
+        Ok(fetched)
+    }
+
+    /// Returns the tree account and whether it was read before this call
+    pub async fn get(
+        &self,
+        tree: &Pubkey,
+    ) -> Result<(Arc<CachedTree>, bool), IntegrityVerificationError> {
+        let entry = self.entry(tree);
+        let mut cached = entry.lock().await;
+        if let Some(fresh) = cached
+            .as_ref()
+            .filter(|cached| cached.fetched_at.elapsed() <= self.max_age)
+        {
+            return Ok((fresh.clone(), true));
+        }
+        Ok((self.fetch(tree, &mut cached).await?, false))
+    }
+
+    /// Reads the tree account again, not older than `outdated`, unless another
+    /// validation replaced `outdated` in the meantime
+    pub async fn refresh(
+        &self,
+        tree: &Pubkey,
+        outdated: &Arc<CachedTree>,
+    ) -> Result<Arc<CachedTree>, IntegrityVerificationError> {
+        let entry = self.entry(tree);
+        let mut cached = entry.lock().await;
+        if let Some(replaced) = cached
+            .as_ref()
+            .filter(|cached| !Arc::ptr_eq(cached, outdated))
+        {
+            return Ok(replaced.clone());
+        }
+        debug!("Refetching tree {} read at slot {}", tree, outdated.slot);
+        self.fetch(tree, &mut cached).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TreeCache;
+    use crate::{
+        error::IntegrityVerificationError,
+        interfaces::{AccountProvider, MockAccountProvider},
+    };
+    use async_trait::async_trait;
+    use mockall::predicate::{always, eq};
+    use solana_program::pubkey::Pubkey;
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    };
+
+    // Takes a while to answer, every fetch returns a later slot
+    #[derive(Default)]
+    struct SlowProvider {
+        fetches: AtomicU64,
+    }
+
+    #[async_trait]
+    impl AccountProvider for SlowProvider {
+        async fn get_account_data(
+            &self,
+            pubkey: &Pubkey,
+        ) -> Result<Option<Vec<u8>>, IntegrityVerificationError> {
+            Ok(self
+                .get_account_at_slot(pubkey, None)
+                .await?
+                .map(|(data, _)| data))
+        }
+
+        async fn get_account_at_slot(
+            &self,
+            _pubkey: &Pubkey,
+            _min_slot: Option<u64>,
+        ) -> Result<Option<(Vec<u8>, u64)>, IntegrityVerificationError> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let slot = self.fetches.fetch_add(1, Ordering::Relaxed) + 1;
+            Ok(Some((vec![], slot)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tree_cache() {
+        let tree = Pubkey::new_unique();
+        let mut provider = MockAccountProvider::new();
+        provider
+            .expect_get_account_at_slot()
+            .with(eq(tree), eq(None))
+            .times(1)
+            .returning(|_, _| Ok(Some((vec![1], 10))));
+        provider
+            .expect_get_account_at_slot()
+            .with(eq(tree), eq(Some(10)))
+            .times(1)
+            .returning(|_, _| Ok(Some((vec![2], 12))));
+        provider
+            .expect_get_account_at_slot()
+            .with(always(), always())
+            .returning(|_, _| Ok(None));
+        let cache = TreeCache::new(Box::new(provider), Duration::from_secs(60));
+
+        let (first, cached) = cache.get(&tree).await.unwrap();
+        assert!(!cached);
+        assert_eq!((vec![1], 10), (first.data.clone(), first.slot));
+        let (second, cached) = cache.get(&tree).await.unwrap();
+        assert!(cached);
+        assert_eq!(first, second);
+
+        let refreshed = cache.refresh(&tree, &first).await.unwrap();
+        assert_eq!((vec![2], 12), (refreshed.data.clone(), refreshed.slot));
+        // Already replaced by another validation
+        let again = cache.refresh(&tree, &first).await.unwrap();
+        assert_eq!(refreshed, again);
+        assert_eq!(refreshed, cache.get(&tree).await.unwrap().0);
+
+        assert!(cache.get(&Pubkey::new_unique()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tree_cache_fetches() {
+        let tree = Pubkey::new_unique();
+        let cache = TreeCache::new(Box::<SlowProvider>::default(), Duration::from_millis(50));
+
+        // Validations of the same tree share a fetch
+        let (first, second) = tokio::join!(cache.get(&tree), cache.get(&tree));
+        let ((first, _), (second, _)) = (first.unwrap(), second.unwrap());
+        assert_eq!(1, first.slot);
+        assert_eq!(first, second);
+
+        // A read older than the max age is not used any more
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let (expired, cached) = cache.get(&tree).await.unwrap();
+        assert!(!cached);
+        assert_eq!(2, expired.slot);
+    }
+}
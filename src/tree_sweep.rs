@@ -0,0 +1,394 @@
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::Arc};
+
+use crate::{
+    account_provider::{account_provider_from_config, AccountFetches, AccountFetchesReport},
+    api::IntegrityVerificationApi,
+    config::IntegrityVerificationConfig,
+    diff_checker::{GET_ASSET_METHOD, GET_ASSET_PROOF_METHOD},
+    error::IntegrityVerificationError,
+    params_generation::{generate_get_asset_params, generate_get_asset_proof_params},
+    proof_validator::{ProofStatus, ProofValidator},
+    requests::Body,
+};
+use serde_json::{json, Value};
+use solana_program::{pubkey, pubkey::Pubkey};
+use tokio::task::{JoinError, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+const BUBBLEGUM_PROGRAM_ID: Pubkey = pubkey!("BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY");
+// Anchor discriminator, tree creator, tree delegate and total mint capacity
+// come before the number of minted leaves in the Bubblegum tree config
+const TREE_CONFIG_NUM_MINTED_OFFSET: usize = 8 + 32 + 32 + 8;
+
+/// Returns the number of leaves minted to a tree from its Bubblegum tree config
+fn parse_num_minted(tree_config: &[u8]) -> Option<u64> {
+    let bytes =
+        tree_config.get(TREE_CONFIG_NUM_MINTED_OFFSET..TREE_CONFIG_NUM_MINTED_OFFSET + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Id of the compressed asset minted to `leaf_index` of `tree`
+pub fn asset_id(tree: &Pubkey, leaf_index: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"asset", tree.as_ref(), &leaf_index.to_le_bytes()],
+        &BUBBLEGUM_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Outcome of checking a single leaf on a single host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LeafOutcome {
+    Proof(ProofStatus),
+    Burnt,
+    // `getAsset` or `getAssetProof` return no result
+    Missing,
+    // `getAsset` places the asset in another tree or at another leaf
+    WrongLeaf,
+    Error,
+}
+
+impl LeafOutcome {
+    fn is_ok(&self) -> bool {
+        match self {
+            LeafOutcome::Proof(status) => status.is_valid(),
+            LeafOutcome::Burnt => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for LeafOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LeafOutcome::Proof(status) => write!(f, "{}", status),
+            LeafOutcome::Burnt => write!(f, "burnt"),
+            LeafOutcome::Missing => write!(f, "missing"),
+            LeafOutcome::WrongLeaf => write!(f, "wrong leaf"),
+            LeafOutcome::Error => write!(f, "error"),
+        }
+    }
+}
+
+struct LeafReport {
+    leaf_index: u64,
+    reference: LeafOutcome,
+    testing: LeafOutcome,
+    // Both hosts returned proofs of different leaves
+    leaves_differ: bool,
+}
+
+struct Sweep {
+    api: IntegrityVerificationApi,
+    proof_validator: ProofValidator,
+    reference_host: String,
+    testing_host: String,
+    tree: Pubkey,
+}
+
+impl Sweep {
+    async fn request(&self, host: &str, body: &Body) -> Result<Value, IntegrityVerificationError> {
+        self.api.make_request(host, &json!(body).to_string()).await
+    }
+
+    /// Returns the outcome of the leaf on `host` and the leaf of its proof
+    async fn check_host(&self, host: &str, leaf_index: u64) -> (LeafOutcome, Option<String>) {
+        let asset_id = asset_id(&self.tree, leaf_index).to_string();
+        let get_asset = Body::new(
+            GET_ASSET_METHOD,
+            json!(generate_get_asset_params(asset_id.clone())),
+        );
+        let asset = match self.request(host, &get_asset).await {
+            Ok(asset) => asset,
+            Err(e) => {
+                error!("{}: getAsset of leaf {}: {}", host, leaf_index, e);
+                return (LeafOutcome::Error, None);
+            }
+        };
+        let result = &asset["result"];
+        if result.is_null() {
+            return (LeafOutcome::Missing, None);
+        }
+        if result["compression"]["tree"] != self.tree.to_string()
+            || result["compression"]["leaf_id"] != leaf_index
+        {
+            return (LeafOutcome::WrongLeaf, None);
+        }
+        if result["burnt"] == true {
+            return (LeafOutcome::Burnt, None);
+        }
+
+        let get_asset_proof = Body::new(
+            GET_ASSET_PROOF_METHOD,
+            json!(generate_get_asset_proof_params(asset_id)),
+        );
+        let proof = match self.request(host, &get_asset_proof).await {
+            Ok(proof) => proof,
+            Err(e) => {
+                error!("{}: getAssetProof of leaf {}: {}", host, leaf_index, e);
+                return (LeafOutcome::Error, None);
+            }
+        };
+        if proof["result"].is_null() {
+            return (LeafOutcome::Missing, None);
+        }
+        let leaf = proof["result"]["leaf"].as_str().map(str::to_string);
+        match self.proof_validator.check_proof(&proof, &asset).await {
+            Ok(status) => (LeafOutcome::Proof(status), leaf),
+            Err(e) => {
+                error!("{}: check proof of leaf {}: {}", host, leaf_index, e);
+                (LeafOutcome::Error, leaf)
+            }
+        }
+    }
+
+    async fn check_leaf(&self, leaf_index: u64) -> LeafReport {
+        let ((reference, reference_leaf), (testing, testing_leaf)) = tokio::join!(
+            self.check_host(&self.reference_host, leaf_index),
+            self.check_host(&self.testing_host, leaf_index)
+        );
+
+        LeafReport {
+            leaf_index,
+            reference,
+            testing,
+            leaves_differ: reference_leaf.is_some()
+                && testing_leaf.is_some()
+                && reference_leaf != testing_leaf,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SweepSummary {
+    swept: u64,
+    reference: BTreeMap<LeafOutcome, u64>,
+    testing: BTreeMap<LeafOutcome, u64>,
+    leaves_differ: u64,
+    account_fetches: BTreeMap<String, AccountFetches>,
+}
+
+impl SweepSummary {
+    /// Records the report of a leaf task, a task that failed counts as an
+    /// error on both hosts so every leaf swept is accounted for
+    fn record_joined(&mut self, report: Result<LeafReport, JoinError>) {
+        match report {
+            Ok(report) => self.record(report),
+            Err(e) => {
+                error!("Leaf task failed: {}", e);
+                self.swept += 1;
+                *self.reference.entry(LeafOutcome::Error).or_insert(0) += 1;
+                *self.testing.entry(LeafOutcome::Error).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn record(&mut self, report: LeafReport) {
+        self.swept += 1;
+        *self.reference.entry(report.reference).or_insert(0) += 1;
+        *self.testing.entry(report.testing).or_insert(0) += 1;
+        if report.leaves_differ {
+            self.leaves_differ += 1;
+        }
+
+        if !report.testing.is_ok() || !report.reference.is_ok() || report.leaves_differ {
+            warn!(
+                "Leaf {}: reference {}, testing {}{}",
+                report.leaf_index,
+                report.reference,
+                report.testing,
+                if report.leaves_differ {
+                    ", leaves differ"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+}
+
+impl fmt::Display for SweepSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Swept leaves: {}", self.swept)?;
+        for (host, outcomes) in [("Reference", &self.reference), ("Testing", &self.testing)] {
+            write!(f, "\n---\n{} host:\noutcome - number", host)?;
+            for (outcome, number) in outcomes {
+                write!(f, "\n{} - {}", outcome, number)?;
+            }
+        }
+        write!(
+            f,
+            "\n---\nLeaves differing between hosts: {}{}",
+            self.leaves_differ,
+            AccountFetchesReport(&self.account_fetches)
+        )
+    }
+}
+
+/// Checks `getAsset` and `getAssetProof` of every leaf minted to the tree of
+/// `config` on both hosts
+pub async fn run_tree_sweep(
+    config: &IntegrityVerificationConfig,
+    cancel_token: CancellationToken,
+) -> Result<(), IntegrityVerificationError> {
+    let tree =
+        Pubkey::from_str(config.sweep_tree.as_deref().ok_or_else(|| {
+            IntegrityVerificationError::ValidateConfig("sweep_tree".to_string())
+        })?)?;
+
+    let tree_config_id = Pubkey::find_program_address(&[tree.as_ref()], &BUBBLEGUM_PROGRAM_ID).0;
+    let tree_config = account_provider_from_config(config)
+        .get_account_data(&tree_config_id)
+        .await?
+        .ok_or(IntegrityVerificationError::NullAssetAccount(
+            tree_config_id.to_string(),
+        ))?;
+    let num_minted = parse_num_minted(&tree_config).ok_or(
+        IntegrityVerificationError::CannotGetResponseField("num_minted".to_string()),
+    )?;
+    info!("Sweeping {} leaves of tree {}", num_minted, tree);
+
+    let sweep = Arc::new(Sweep {
+        api: IntegrityVerificationApi::new(),
+        proof_validator: ProofValidator::new(config),
+        reference_host: config.reference_host.clone(),
+        testing_host: config.testing_host.clone(),
+        tree,
+    });
+    let mut summary = SweepSummary::default();
+    let mut tasks = JoinSet::new();
+    for leaf_index in 0..num_minted {
+        if cancel_token.is_cancelled() {
+            warn!("Sweep was interrupted at leaf {}", leaf_index);
+            break;
+        }
+        if tasks.len() >= config.sweep_concurrency {
+            if let Some(report) = tasks.join_next().await {
+                summary.record_joined(report);
+            }
+        }
+        let sweep = sweep.clone();
+        tasks.spawn(async move { sweep.check_leaf(leaf_index).await });
+    }
+    while let Some(report) = tasks.join_next().await {
+        summary.record_joined(report);
+    }
+
+    summary.account_fetches = sweep.proof_validator.account_fetches();
+    println!("Tree sweep of {}:\n{}", tree, summary);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        asset_id, parse_num_minted, LeafOutcome, Sweep, SweepSummary, TREE_CONFIG_NUM_MINTED_OFFSET,
+    };
+    use crate::account_provider::InMemoryAccountProvider;
+    use crate::api::{test_json_rpc_server, IntegrityVerificationApi};
+    use crate::compressed_leaf::{leaf_hash, test_compressed_asset};
+    use crate::merkle_tree::test_tree_account;
+    use crate::proof_validator::{ProofStatus, ProofValidator};
+    use serde_json::{json, Value};
+    use solana_program::pubkey::Pubkey;
+    use spl_concurrent_merkle_tree::node::empty_node;
+    use std::{str::FromStr, time::Duration};
+
+    #[test]
+    fn test_tree_config() {
+        let mut tree_config = vec![0; TREE_CONFIG_NUM_MINTED_OFFSET + 8 + 2];
+        tree_config[TREE_CONFIG_NUM_MINTED_OFFSET..TREE_CONFIG_NUM_MINTED_OFFSET + 8]
+            .copy_from_slice(&1234u64.to_le_bytes());
+        assert_eq!(Some(1234), parse_num_minted(&tree_config));
+        assert_eq!(None, parse_num_minted(&tree_config[..40]));
+
+        // Derived apart from this crate from the "asset", tree and little endian
+        // leaf index seeds of the Bubblegum program
+        let tree = Pubkey::from_str("US517G5965aydkZ46HS38QLi7UQiSojurfbQfKCELFx").unwrap();
+        assert_eq!(
+            "gPUs18eKDJ33U52bkDvoDEN9kahPpbZ9HtfhvMWZH1Q",
+            asset_id(&tree, 0).to_string()
+        );
+        assert_eq!(
+            "9Yb2hG1yoS2TGAyTp8mUe3rDAevFuRsS9tgw6Hh236gR",
+            asset_id(&tree, 42).to_string()
+        );
+    }
+
+    fn tree_asset(tree: &Pubkey, leaf_index: u64) -> Value {
+        let mut asset = test_compressed_asset(leaf_index);
+        asset["result"]["id"] = asset_id(tree, leaf_index).to_string().into();
+        asset["result"]["compression"]["tree"] = tree.to_string().into();
+        asset
+    }
+
+    #[tokio::test]
+    async fn test_check_host() {
+        let tree = Pubkey::new_unique();
+        let proven = tree_asset(&tree, 0);
+        let leaf = leaf_hash(&proven["result"]).unwrap();
+        let mut misplaced = tree_asset(&tree, 2);
+        misplaced["result"]["compression"]["leaf_id"] = 5.into();
+        let mut burnt = tree_asset(&tree, 3);
+        burnt["result"]["burnt"] = true.into();
+
+        let node = |node: &[u8; 32]| Pubkey::new_from_array(*node).to_string();
+        let proof = [leaf, empty_node(0), empty_node(1)];
+        let root = spl_concurrent_merkle_tree::hash::recompute(leaf, &proof[1..], 0);
+        let proof = json!({"result": {
+            "tree_id": tree.to_string(),
+            "leaf": node(&leaf),
+            "root": node(&root),
+            "proof": proof[1..].iter().map(node).collect::<Vec<_>>(),
+        }});
+
+        let (host, _) = test_json_rpc_server(Duration::ZERO, move |request| {
+            let id = request["params"]["id"].as_str().unwrap();
+            let asset = [&proven, &misplaced, &burnt]
+                .into_iter()
+                .find(|asset| asset["result"]["id"] == id);
+            match (request["method"].as_str(), asset) {
+                (Some("getAsset"), Some(asset)) => asset.clone(),
+                (Some("getAssetProof"), Some(_)) => proof.clone(),
+                _ => json!({"result": null}),
+            }
+        })
+        .await;
+
+        let mut accounts = InMemoryAccountProvider::default();
+        accounts.insert(tree, test_tree_account::<2, 8>(&[leaf]));
+        let sweep = Sweep {
+            api: IntegrityVerificationApi::new(),
+            proof_validator: ProofValidator::with_account_provider(
+                Box::new(accounts),
+                Duration::from_secs(60),
+            ),
+            reference_host: host.clone(),
+            testing_host: host.clone(),
+            tree,
+        };
+
+        let (outcome, proof_leaf) = sweep.check_host(&host, 0).await;
+        assert_eq!(LeafOutcome::Proof(ProofStatus::ValidCurrent), outcome);
+        assert_eq!(Some(node(&leaf)), proof_leaf);
+        assert_eq!(LeafOutcome::Missing, sweep.check_host(&host, 1).await.0);
+        assert_eq!(LeafOutcome::WrongLeaf, sweep.check_host(&host, 2).await.0);
+        assert_eq!(LeafOutcome::Burnt, sweep.check_host(&host, 3).await.0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_leaf_task_is_counted() {
+        let mut tasks = tokio::task::JoinSet::new();
+        tasks.spawn(async { panic!("leaf task failed") });
+        let mut summary = SweepSummary::default();
+        while let Some(report) = tasks.join_next().await {
+            summary.record_joined(report);
+        }
+
+        assert_eq!(1, summary.swept);
+        assert_eq!(Some(&1), summary.reference.get(&LeafOutcome::Error));
+        assert_eq!(Some(&1), summary.testing.get(&LeafOutcome::Error));
+    }
+}